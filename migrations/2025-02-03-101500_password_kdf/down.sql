@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "users"
+DROP COLUMN IF EXISTS "password_scheme";
+
+-- Argon2id hashes do not fit into 64 characters, so they are dropped on rollback
+UPDATE "users" SET "password" = '' WHERE LENGTH("password") > 64;
+
+ALTER TABLE "users"
+ALTER COLUMN "password" TYPE VARCHAR(64);
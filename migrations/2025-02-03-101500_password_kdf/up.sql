@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+-- Argon2id PHC strings carry their own parameters and are longer than 64 characters
+ALTER TABLE "users"
+ALTER COLUMN "password" TYPE VARCHAR(255);
+
+-- 0: legacy SHA-256, 1: Argon2id
+ALTER TABLE "users"
+ADD COLUMN "password_scheme" INTEGER NOT NULL DEFAULT 0;
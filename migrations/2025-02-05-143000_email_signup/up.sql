@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- One-time codes for signing up with an email address instead of a register token
+CREATE TABLE "email_signup_code" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "email" VARCHAR(255) NOT NULL UNIQUE,
+    "code_hash" CHAR(64) NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY("id")
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS "password_reset_index_user";
+DROP TABLE IF EXISTS "password_reset";
+
+ALTER TABLE "users"
+DROP COLUMN IF EXISTS "session_version";
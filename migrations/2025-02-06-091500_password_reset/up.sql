@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- Bumped whenever all sessions of a user should be invalidated, e.g. on password reset
+ALTER TABLE "users"
+ADD COLUMN "session_version" INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE "password_reset" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "user" INTEGER NOT NULL,
+    "token_hash" CHAR(64) NOT NULL UNIQUE,
+    "used" BOOLEAN NOT NULL DEFAULT false,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY("id"),
+	CONSTRAINT "fk_user_password_reset"
+        FOREIGN KEY ("user") REFERENCES "users" ("id")
+        ON DELETE CASCADE
+);
+
+CREATE INDEX "password_reset_index_user"
+ON "password_reset" ("user");
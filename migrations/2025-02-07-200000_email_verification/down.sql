@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "announcement";
+
+DROP INDEX IF EXISTS "email_index_verified";
+
+ALTER TABLE "email"
+DROP COLUMN IF EXISTS "verified_at",
+DROP COLUMN IF EXISTS "code_hash",
+DROP COLUMN IF EXISTS "code_attempts",
+DROP COLUMN IF EXISTS "code_expires_at";
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+ALTER TABLE "email"
+ADD COLUMN "verified_at" TIMESTAMPTZ,
+ADD COLUMN "code_hash" CHAR(64),
+ADD COLUMN "code_attempts" INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN "code_expires_at" TIMESTAMPTZ;
+
+-- 为已验证的邮箱创建索引，用于发送通知
+CREATE INDEX "email_index_verified"
+ON "email" ("user")
+WHERE "verified_at" IS NOT NULL;
+
+CREATE TABLE "announcement" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "author" INTEGER NOT NULL,
+    "title" VARCHAR(255) NOT NULL,
+    "content" TEXT NOT NULL,
+    "time" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("id")
+);
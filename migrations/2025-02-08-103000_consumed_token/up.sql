@@ -0,0 +1,8 @@
+-- 已使用的注册令牌，防止重放
+CREATE TABLE "consumed_token" (
+    "token_hash" CHAR(64) NOT NULL,
+    "consumed_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("token_hash")
+);
+
+CREATE INDEX "consumed_token_index_time" ON "consumed_token" ("consumed_at");
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "totp_recovery_code";
+
+ALTER TABLE "users"
+DROP COLUMN IF EXISTS "totp_secret",
+DROP COLUMN IF EXISTS "totp_enabled_at",
+DROP COLUMN IF EXISTS "totp_last_step";
@@ -0,0 +1,21 @@
+-- RFC 6238 TOTP 二次验证
+-- totp_secret 为 base32 编码的密钥；totp_enabled_at 为空表示尚未确认（或未启用）
+-- totp_last_step 为最后一次使用的时间步，防止同一验证码被重复使用
+ALTER TABLE "users"
+ADD COLUMN "totp_secret" VARCHAR(64),
+ADD COLUMN "totp_enabled_at" TIMESTAMPTZ,
+ADD COLUMN "totp_last_step" BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE "totp_recovery_code" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "user" INTEGER NOT NULL,
+    "code_hash" CHAR(64) NOT NULL,
+    "used_at" TIMESTAMPTZ,
+	PRIMARY KEY("id")
+);
+
+CREATE INDEX "totp_recovery_code_index_user" ON "totp_recovery_code" ("user");
+
+ALTER TABLE "totp_recovery_code"
+ADD FOREIGN KEY("user") REFERENCES "users"("id")
+ON UPDATE NO ACTION ON DELETE CASCADE;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "team_invite";
+
+ALTER TABLE "team"
+DROP COLUMN IF EXISTS "captain";
@@ -0,0 +1,26 @@
+-- 队长创建的入队邀请，可设置有效期与最大使用次数
+-- 已有队伍的队长为编号最小的成员
+ALTER TABLE "team"
+ADD COLUMN "captain" INTEGER;
+
+UPDATE "team"
+SET "captain" = (SELECT MIN("users"."id") FROM "users" WHERE "users"."team" = "team"."id");
+
+CREATE TABLE "team_invite" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "team" INTEGER NOT NULL,
+    "token_hash" CHAR(64) NOT NULL UNIQUE,
+    "created_by" INTEGER NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    "max_uses" INTEGER NOT NULL,
+    "uses" INTEGER NOT NULL DEFAULT 0,
+    "revoked" BOOLEAN NOT NULL DEFAULT false,
+	PRIMARY KEY("id"),
+	CONSTRAINT "fk_team_team_invite"
+        FOREIGN KEY ("team") REFERENCES "team" ("id")
+        ON DELETE CASCADE
+);
+
+CREATE INDEX "team_invite_index_team"
+ON "team_invite" ("team");
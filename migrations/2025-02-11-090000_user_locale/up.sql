@@ -0,0 +1,3 @@
+-- 用户的语言偏好，如 zh-CN、en；为空时按 Accept-Language 选择
+ALTER TABLE "users"
+ADD COLUMN "locale" VARCHAR(16);
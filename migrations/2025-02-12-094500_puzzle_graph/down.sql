@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "puzzle_prerequisite";
+
+ALTER TABLE "puzzle"
+DROP COLUMN IF EXISTS "unlock_count",
+DROP COLUMN IF EXISTS "unlock_rule",
+DROP COLUMN IF EXISTS "round";
+
+DROP TABLE IF EXISTS "puzzle_round";
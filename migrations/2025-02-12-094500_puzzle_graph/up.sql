@@ -0,0 +1,32 @@
+-- 谜题分轮开放；轮次开放后，按解锁规则免费解锁其中的谜题
+CREATE TABLE "puzzle_round" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+	"title" VARCHAR(64) NOT NULL,
+	"opens_at" TIMESTAMPTZ NOT NULL,
+	PRIMARY KEY("id")
+);
+
+-- unlock_rule: 0 付费解锁（原有行为），1 轮次开放即解锁，
+-- 2 本轮解出 unlock_count 题后解锁，3 解出全部前置谜题后解锁
+ALTER TABLE "puzzle"
+ADD COLUMN "round" INTEGER,
+ADD COLUMN "unlock_rule" INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN "unlock_count" INTEGER NOT NULL DEFAULT 0,
+ADD CONSTRAINT "fk_puzzle_round_puzzle"
+    FOREIGN KEY ("round") REFERENCES "puzzle_round" ("id")
+    ON DELETE SET NULL;
+
+CREATE TABLE "puzzle_prerequisite" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "puzzle" INTEGER NOT NULL,
+    "requires" INTEGER NOT NULL,
+	PRIMARY KEY("id"),
+    CONSTRAINT "fk_puzzle_puzzle_prerequisite"
+        FOREIGN KEY ("puzzle") REFERENCES "puzzle" ("id")
+        ON DELETE CASCADE,
+    CONSTRAINT "fk_requires_puzzle_prerequisite"
+        FOREIGN KEY ("requires") REFERENCES "puzzle" ("id")
+        ON DELETE CASCADE,
+    CONSTRAINT "unique_puzzle_requires"
+        UNIQUE ("puzzle", "requires")
+);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "decipher"
+DROP COLUMN IF EXISTS "available_until",
+DROP COLUMN IF EXISTS "available_from";
+
+ALTER TABLE "puzzle"
+DROP COLUMN IF EXISTS "available_until",
+DROP COLUMN IF EXISTS "available_from";
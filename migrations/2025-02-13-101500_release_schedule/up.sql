@@ -0,0 +1,8 @@
+-- 谜题与解密钥匙的开放时间窗口，为空则不限；管理员可提前发布
+ALTER TABLE "puzzle"
+ADD COLUMN "available_from" TIMESTAMPTZ,
+ADD COLUMN "available_until" TIMESTAMPTZ;
+
+ALTER TABLE "decipher"
+ADD COLUMN "available_from" TIMESTAMPTZ,
+ADD COLUMN "available_until" TIMESTAMPTZ;
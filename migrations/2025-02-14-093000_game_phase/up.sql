@@ -0,0 +1,9 @@
+-- 比赛阶段的切换记录，最新一条为当前阶段；没有记录时视为进行中
+-- phase: 0 赛前，1 进行中，2 封榜，3 已结束，4 赛后开放
+CREATE TABLE "game_phase" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "phase" INTEGER NOT NULL,
+    "changed_by" INTEGER NOT NULL,
+    "changed_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("id")
+);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "wrong_answer_cnt"
+DROP COLUMN IF EXISTS "updated_at",
+DROP COLUMN IF EXISTS "wrong_attempts";
+
+ALTER TABLE "puzzle_round"
+DROP COLUMN IF EXISTS "penalty_schedule";
+
+ALTER TABLE "puzzle"
+DROP COLUMN IF EXISTS "penalty_schedule";
+
+DROP TABLE IF EXISTS "penalty_schedule";
@@ -0,0 +1,37 @@
+-- 错误答案的惩罚方案，可指定给谜题或轮次；都未指定时使用默认方案
+-- time_penalty / token_penalty: 按惩罚等级的冷却秒数与扣除代币，超出后取最后一项
+-- free_attempts: 开始惩罚前可免费尝试的次数
+-- decay_minutes: 每隔多久没有答错，惩罚等级降低一级，为空则不降低
+CREATE TABLE "penalty_schedule" (
+	"id" INTEGER NOT NULL UNIQUE GENERATED BY DEFAULT AS IDENTITY,
+    "time_penalty" BIGINT[] NOT NULL,
+    "token_penalty" BIGINT[] NOT NULL,
+    "free_attempts" INTEGER NOT NULL DEFAULT 0,
+    "decay_minutes" INTEGER,
+    "reset_time_on_mid_answer" BOOLEAN NOT NULL DEFAULT false,
+	PRIMARY KEY("id"),
+    CONSTRAINT "penalty_schedule_not_empty"
+        CHECK (cardinality("time_penalty") > 0 AND cardinality("token_penalty") > 0),
+    -- 元素不可为空（`0 <= ALL` 对 NULL 不报错）且不可为负
+    CONSTRAINT "penalty_schedule_time_non_negative"
+        CHECK (array_position("time_penalty", NULL) IS NULL AND 0 <= ALL("time_penalty")),
+    CONSTRAINT "penalty_schedule_token_non_negative"
+        CHECK (array_position("token_penalty", NULL) IS NULL AND 0 <= ALL("token_penalty"))
+);
+
+ALTER TABLE "puzzle"
+ADD COLUMN "penalty_schedule" INTEGER,
+ADD CONSTRAINT "fk_penalty_schedule_puzzle"
+    FOREIGN KEY ("penalty_schedule") REFERENCES "penalty_schedule" ("id")
+    ON DELETE SET NULL;
+
+ALTER TABLE "puzzle_round"
+ADD COLUMN "penalty_schedule" INTEGER,
+ADD CONSTRAINT "fk_penalty_schedule_puzzle_round"
+    FOREIGN KEY ("penalty_schedule") REFERENCES "penalty_schedule" ("id")
+    ON DELETE SET NULL;
+
+-- 累计答错次数与上次答错的时间，用于免费尝试与等级衰减
+ALTER TABLE "wrong_answer_cnt"
+ADD COLUMN "wrong_attempts" INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
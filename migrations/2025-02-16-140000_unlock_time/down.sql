@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "unlock"
+DROP COLUMN IF EXISTS "unlocked_at";
@@ -0,0 +1,9 @@
+-- 解锁时间，用于统计解锁后到解出的用时；已有记录取购买的时间
+ALTER TABLE "unlock"
+ADD COLUMN "unlocked_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE "unlock"
+SET "unlocked_at" = "transaction"."time"
+FROM "transaction"
+WHERE "transaction"."team" = "unlock"."team"
+    AND "transaction"."purchase_ref" = "unlock"."decipher";
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS "trigger_puzzle_summary_puzzle" ON "puzzle";
+DROP TRIGGER IF EXISTS "trigger_puzzle_summary_unlocked" ON "unlock";
+DROP TRIGGER IF EXISTS "trigger_puzzle_summary_passed" ON "submission";
+
+DROP FUNCTION IF EXISTS refresh_puzzle_summary_on_puzzle();
+DROP FUNCTION IF EXISTS count_puzzle_summary_unlocked();
+DROP FUNCTION IF EXISTS count_puzzle_summary_passed();
+DROP FUNCTION IF EXISTS refresh_puzzle_summary(INTEGER);
+
+DROP TABLE IF EXISTS "puzzle_summary";
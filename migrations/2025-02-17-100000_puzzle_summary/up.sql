@@ -0,0 +1,88 @@
+-- 每道谜题的通过队伍数与解锁队伍数，由触发器维护，/puzzle_status 不必每次全表统计
+CREATE TABLE "puzzle_summary" (
+    "puzzle" INTEGER NOT NULL,
+    "teams_passed" BIGINT NOT NULL DEFAULT 0,
+    "teams_unlocked" BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY("puzzle"),
+    CONSTRAINT "fk_puzzle_puzzle_summary"
+        FOREIGN KEY ("puzzle") REFERENCES "puzzle" ("id")
+        ON DELETE CASCADE
+);
+
+-- 重新统计一道谜题，用于初始化以及谜题的 decipher 变化时
+CREATE OR REPLACE FUNCTION refresh_puzzle_summary(puzzle_id INTEGER)
+RETURNS VOID AS $$
+BEGIN
+    INSERT INTO "puzzle_summary" ("puzzle", "teams_passed", "teams_unlocked")
+    SELECT
+        p.id,
+        (SELECT COUNT(DISTINCT s.team) FROM submission AS s WHERE s.puzzle = p.id AND s.depth = 0),
+        (SELECT COUNT(DISTINCT u.team) FROM unlock AS u WHERE u.decipher = p.decipher)
+    FROM puzzle AS p
+    WHERE p.id = puzzle_id
+    ON CONFLICT ("puzzle") DO UPDATE
+    SET "teams_passed" = EXCLUDED."teams_passed",
+        "teams_unlocked" = EXCLUDED."teams_unlocked";
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT refresh_puzzle_summary(id) FROM puzzle;
+
+-- (team, puzzle, depth) 唯一，每条 depth = 0 的提交即一支新通过的队伍
+CREATE OR REPLACE FUNCTION count_puzzle_summary_passed()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' AND NEW.depth = 0 THEN
+        INSERT INTO "puzzle_summary" ("puzzle", "teams_passed")
+        VALUES (NEW.puzzle, 1)
+        ON CONFLICT ("puzzle") DO UPDATE
+        SET "teams_passed" = "puzzle_summary"."teams_passed" + 1;
+    ELSIF TG_OP = 'DELETE' AND OLD.depth = 0 THEN
+        UPDATE "puzzle_summary"
+        SET "teams_passed" = "teams_passed" - 1
+        WHERE "puzzle" = OLD.puzzle;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "trigger_puzzle_summary_passed"
+AFTER INSERT OR DELETE ON "submission"
+FOR EACH ROW
+EXECUTE FUNCTION count_puzzle_summary_passed();
+
+-- (team, decipher) 唯一，解锁计入使用该 decipher 的每道谜题
+CREATE OR REPLACE FUNCTION count_puzzle_summary_unlocked()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO "puzzle_summary" ("puzzle", "teams_unlocked")
+        SELECT id, 1 FROM puzzle WHERE decipher = NEW.decipher
+        ON CONFLICT ("puzzle") DO UPDATE
+        SET "teams_unlocked" = "puzzle_summary"."teams_unlocked" + 1;
+    ELSE
+        UPDATE "puzzle_summary"
+        SET "teams_unlocked" = "teams_unlocked" - 1
+        WHERE "puzzle" IN (SELECT id FROM puzzle WHERE decipher = OLD.decipher);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "trigger_puzzle_summary_unlocked"
+AFTER INSERT OR DELETE ON "unlock"
+FOR EACH ROW
+EXECUTE FUNCTION count_puzzle_summary_unlocked();
+
+CREATE OR REPLACE FUNCTION refresh_puzzle_summary_on_puzzle()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM refresh_puzzle_summary(NEW.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "trigger_puzzle_summary_puzzle"
+AFTER INSERT OR UPDATE OF "decipher" ON "puzzle"
+FOR EACH ROW
+EXECUTE FUNCTION refresh_puzzle_summary_on_puzzle();
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "oracle"
+DROP COLUMN IF EXISTS "created_at";
@@ -0,0 +1,21 @@
+-- 神谕的创建时间，用于按时间统计；已有记录取扣费的时间，同一队伍同一题目按顺序对应
+ALTER TABLE "oracle"
+ADD COLUMN "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+WITH "o" AS (
+    SELECT "id", "team", "puzzle",
+        ROW_NUMBER() OVER (PARTITION BY "team", "puzzle" ORDER BY "id") AS "n"
+    FROM "oracle"
+), "t" AS (
+    SELECT "team", "desp", "time",
+        ROW_NUMBER() OVER (PARTITION BY "team", "desp" ORDER BY "id") AS "n"
+    FROM "transaction"
+    WHERE "desp" LIKE 'Create oracle on puzzle %'
+)
+UPDATE "oracle"
+SET "created_at" = "t"."time"
+FROM "o"
+JOIN "t" ON "t"."team" = "o"."team"
+    AND "t"."desp" = 'Create oracle on puzzle ' || "o"."puzzle"
+    AND "t"."n" = "o"."n"
+WHERE "oracle"."id" = "o"."id";
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::util::api_util::*;
+use crate::util::notify::Notifier;
+use crate::DbPool;
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct AnnounceRequest {
+    title: String,
+    content: String,
+}
+
+impl APIRequest for AnnounceRequest {
+    fn ok(&self) -> bool {
+        !self.title.is_empty() && self.title.len() <= 255 && self.content.len() <= 10000
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct AnnouncementResponse {
+    id: i32,
+    title: String,
+    content: String,
+    time: i64, // unix timestamp in seconds
+}
+
+// [[API]]
+// desp: Post an announcement and mail it to every verified email.
+// Method: POST
+// URL: /staff_announce
+// Request Body: `AnnounceRequest`
+// Response Body: the id of the announcement.
+#[utoipa::path(
+    tag = "announcement",
+    request_body = AnnounceRequest,
+    responses((status = 200, body = i32)),
+)]
+#[post("/staff_announce")]
+async fn staff_announce(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    notifier: web::Data<Arc<Notifier>>,
+    form: web::Json<AnnounceRequest>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::announcement::dsl::*;
+
+    let location = "staff_announce";
+    form.sanity()?;
+
+    let (staff_id, _) = user_privilege_check(&session, PRIVILEGE_STAFF)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let announcement_id: i32 = diesel::insert_into(announcement)
+        .values((
+            author.eq(staff_id),
+            title.eq(&form.title),
+            content.eq(&form.content),
+        ))
+        .returning(id)
+        .get_result(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    notifier.notify_everyone(
+        format!("元样 2025 公告：{}", form.title),
+        form.content.clone(),
+    );
+
+    Ok(HttpResponse::Ok().json(announcement_id))
+}
+
+// [[API]]
+// desp: List all announcements, newest first.
+// Method: GET
+// URL: /announcements
+// Request Body: N/A
+// Response Body: `Vec<AnnouncementResponse>`
+#[utoipa::path(
+    tag = "announcement",
+    responses((status = 200, body = Vec<AnnouncementResponse>)),
+)]
+#[get("/announcements")]
+async fn announcements(pool: web::Data<Arc<DbPool>>) -> Result<impl Responder, APIError> {
+    use crate::schema::announcement::dsl::*;
+
+    let location = "announcements";
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let result: Vec<AnnouncementResponse> = announcement
+        .order(time.desc())
+        .select((id, title, content, time))
+        .load::<(i32, String, String, DateTime<Utc>)>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .map(|(a_id, a_title, a_content, a_time)| AnnouncementResponse {
+            id: a_id,
+            title: a_title,
+            content: a_content,
+            time: a_time.timestamp(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(result))
+}
@@ -1,13 +1,29 @@
 use std::sync::Arc;
 
-use serde::Deserialize;
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
+use crate::api::register::set_loggedin_session;
+use crate::models::User;
+use crate::schema::users;
 use crate::util::api_util::*;
+use crate::util::api_version::enum_json;
+use crate::util::cache::Cache;
+use crate::util::cipher_util;
+use crate::util::config::config;
+use crate::util::mailer::{send_mail, DynMailer, Mail};
 use actix_session::Session;
 use actix_web::{get, post, web, HttpResponse, Responder};
 
-use crate::DbPool;
+use crate::{DbPool, Ext};
 
+#[utoipa::path(
+    tag = "email",
+    responses((status = 200, body = String)),
+)]
 #[get("/my_email")]
 async fn get_email(
     session: Session,
@@ -28,20 +44,25 @@ async fn get_email(
     {
         Ok(HttpResponse::Ok().json(email))
     } else {
-        Ok(HttpResponse::NotFound().finish())
+        Err(APIError::NotFound)
     }
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct EmailRequest {
     pub email: String,
 }
 
 impl APIRequest for EmailRequest {
     fn ok(&self) -> bool {
-        self.email.len() <= 100
+        is_valid_email(&self.email)
     }
 }
 
+#[utoipa::path(
+    tag = "email",
+    request_body = EmailRequest,
+    responses((status = 200)),
+)]
 #[post("/my_email")]
 async fn post_email(
     session: Session,
@@ -58,9 +79,572 @@ async fn post_email(
         .await
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
-    insert_or_update_email(user_id, form.email.clone(), &mut conn)
+    insert_or_update_email(user_id, normalize_email(&form.email), false, &mut conn)
         .await
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
     Ok(HttpResponse::Ok().finish())
 }
+
+const CODE_EXPIRE_MINUTES: i64 = 15;
+const CODE_RESEND_SECONDS: i64 = 60;
+const CODE_MAX_ATTEMPTS: i32 = 5;
+
+pub(crate) fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// A pragmatic subset of RFC 5321 addresses: `local@domain.tld`.
+///
+/// ``` rust
+/// use server::api::email::is_valid_email;
+///
+/// assert!(is_valid_email("lethe@yuanyang.app"));
+/// assert!(is_valid_email("first.last+tag@mail.pku.edu.cn"));
+/// assert!(!is_valid_email("lethe"));
+/// assert!(!is_valid_email("lethe@localhost"));
+/// assert!(!is_valid_email("@yuanyang.app"));
+/// assert!(!is_valid_email("a@b@yuanyang.app"));
+/// assert!(!is_valid_email("lethe@-yuanyang.app"));
+/// assert!(!is_valid_email(".lethe@yuanyang.app"));
+/// assert!(!is_valid_email("lethe@yuanyang.a"));
+/// ```
+pub fn is_valid_email(email: &str) -> bool {
+    let email = email.trim();
+    if email.len() > 100 {
+        return false;
+    }
+
+    let (local, domain) = match email.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+
+    local_ok && domain_ok
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct EmailSignupCodeRequest {
+    pub email: String,
+}
+
+impl APIRequest for EmailSignupCodeRequest {
+    fn ok(&self) -> bool {
+        is_valid_email(&self.email)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum EmailSignupCodeResponse {
+    Sent,
+    // unix timestamp in seconds
+    TryAgainAfter(i64),
+}
+
+// [[API]]
+// desp: Send a one-time code for signing up without a register token.
+// Method: POST
+// URL: /email_signup_code
+// Request Body: `EmailSignupCodeRequest`
+// Response Body: `EmailSignupCodeResponse`
+#[utoipa::path(
+    tag = "email",
+    request_body = EmailSignupCodeRequest,
+    responses((status = 200, body = EmailSignupCodeResponse)),
+)]
+#[post("/email_signup_code")]
+async fn email_signup_code(
+    pool: web::Data<Arc<DbPool>>,
+    mailer: web::Data<DynMailer>,
+    form: web::Json<EmailSignupCodeRequest>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::email_signup_code::dsl::*;
+
+    let location = "email_signup_code";
+    form.sanity()?;
+
+    let address = normalize_email(&form.email);
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let last_sent = email_signup_code
+        .filter(email.eq(&address))
+        .select(created_at)
+        .first::<DateTime<Utc>>(&mut conn)
+        .await
+        .optional()
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    let now = Utc::now();
+    if let Some(last_sent) = last_sent {
+        let resend_at = last_sent + TimeDelta::seconds(CODE_RESEND_SECONDS);
+        if resend_at > now {
+            return Ok(enum_json(EmailSignupCodeResponse::TryAgainAfter(
+                resend_at.timestamp(),
+            )));
+        }
+    }
+
+    let code = cipher_util::gen_one_time_code();
+    let hashed = cipher_util::hash_one_time_code(&code, &address, &config().secrets.login_token);
+    let expires = now + TimeDelta::minutes(CODE_EXPIRE_MINUTES);
+
+    diesel::insert_into(email_signup_code)
+        .values((
+            email.eq(&address),
+            code_hash.eq(&hashed),
+            created_at.eq(now),
+            expires_at.eq(expires),
+        ))
+        .on_conflict(email)
+        .do_update()
+        .set((
+            code_hash.eq(&hashed),
+            attempts.eq(0),
+            created_at.eq(now),
+            expires_at.eq(expires),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    send_mail(
+        &mailer,
+        Mail {
+            to: address,
+            subject: "元样 2025 注册验证码".to_string(),
+            body: format!("您的注册验证码是 {code}，{CODE_EXPIRE_MINUTES} 分钟内有效。"),
+        },
+        location,
+    )
+    .await?;
+
+    Ok(enum_json(EmailSignupCodeResponse::Sent))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct EmailRegisterRequest {
+    pub email: String,
+    pub code: String,
+    // Max 100.
+    pub username: String,
+    // SHA256 of the password.
+    pub password: String,
+}
+
+impl APIRequest for EmailRegisterRequest {
+    fn ok(&self) -> bool {
+        is_valid_email(&self.email)
+            && self.code.len() == 6
+            && self.username.len() <= 100
+            && self.password.len() == 64
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum EmailRegisterResponse {
+    // returns the user id.
+    Success(i32),
+    // The password is reset, but the user enabled the second factor,
+    // so log in with `/login` instead. Returns the user id.
+    SecondFactorRequired(i32),
+    InvalidCode,
+    // Request a new code.
+    Expired,
+}
+
+// [[API]]
+// desp: Verify the code from `/email_signup_code` and set a password.
+//       Signing up again with the same email resets the password and
+//       revokes the other sessions, but does not log in if the user enabled
+//       the second factor.
+// Method: POST
+// URL: /email_register
+// Request Body: `EmailRegisterRequest`
+// Response Body: `EmailRegisterResponse`
+#[utoipa::path(
+    tag = "email",
+    request_body = EmailRegisterRequest,
+    responses((status = 200, body = EmailRegisterResponse)),
+)]
+#[post("/email_register")]
+async fn email_register(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<EmailRegisterRequest>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "email_register";
+    form.sanity()?;
+
+    let address = normalize_email(&form.email);
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let result = conn
+        .transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                use crate::schema::email_signup_code::dsl as code_dsl;
+
+                let record = code_dsl::email_signup_code
+                    .filter(code_dsl::email.eq(&address))
+                    .select((
+                        code_dsl::code_hash,
+                        code_dsl::attempts,
+                        code_dsl::expires_at,
+                    ))
+                    .for_update()
+                    .first::<(String, i32, DateTime<Utc>)>(conn)
+                    .await
+                    .optional()?;
+
+                let (expected_hash, tried, expires) = match record {
+                    Some(record) => record,
+                    None => return Ok((EmailRegisterResponse::InvalidCode, None)),
+                };
+
+                if expires < Utc::now() || tried >= CODE_MAX_ATTEMPTS {
+                    return Ok((EmailRegisterResponse::Expired, None));
+                }
+
+                if cipher_util::hash_one_time_code(
+                    &form.code,
+                    &address,
+                    &config().secrets.login_token,
+                ) != expected_hash
+                {
+                    diesel::update(
+                        code_dsl::email_signup_code.filter(code_dsl::email.eq(&address)),
+                    )
+                    .set(code_dsl::attempts.eq(tried + 1))
+                    .execute(conn)
+                    .await?;
+                    return Ok((EmailRegisterResponse::InvalidCode, None));
+                }
+
+                diesel::delete(code_dsl::email_signup_code.filter(code_dsl::email.eq(&address)))
+                    .execute(conn)
+                    .await?;
+
+                let password = form.password.clone();
+                let (salt, salted_password, scheme) = web::block(move || {
+                    cipher_util::gen_salted_password(&password, &config().secrets.login_token)
+                })
+                .await
+                .map_err(|e| log_server_error(e, location, ERROR_BLOCKING))?;
+
+                let user: User = diesel::insert_into(users::table)
+                    .values((
+                        users::username.eq(&form.username),
+                        users::openid.eq(cipher_util::email_openid(&address)),
+                        users::privilege.eq(PRIVILEGE_MINIMAL),
+                        users::salt.eq(&salt),
+                        users::password.eq(&salted_password),
+                        users::password_scheme.eq(scheme),
+                    ))
+                    .on_conflict(users::openid)
+                    .do_update()
+                    .set((
+                        users::username.eq(&form.username),
+                        users::salt.eq(&salt),
+                        users::password.eq(&salted_password),
+                        users::password_scheme.eq(scheme),
+                        // Sessions opened with the old password are revoked.
+                        users::session_version.eq(users::session_version + 1),
+                    ))
+                    .returning(User::as_returning())
+                    .get_result(conn)
+                    .await?;
+
+                // The code proved the ownership of the address.
+                insert_or_update_email(user.id, address.clone(), true, conn).await?;
+
+                if user.totp_enabled_at.is_some() {
+                    return Ok((EmailRegisterResponse::SecondFactorRequired(user.id), None));
+                }
+                Ok((EmailRegisterResponse::Success(user.id), Some(user)))
+            })
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    let (result, user) = result;
+    if let EmailRegisterResponse::Success(user_id)
+    | EmailRegisterResponse::SecondFactorRequired(user_id) = result
+    {
+        cache.session_version_cache.invalidate(user_id).await;
+    }
+    if let Some(user) = user {
+        session.clear();
+        set_loggedin_session(&mut session, &user, location)?;
+    }
+
+    Ok(enum_json(result))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct EmailStatusResponse {
+    email: String,
+    verified: bool,
+}
+
+// [[API]]
+// desp: The recorded email and whether it is verified.
+// Method: GET
+// URL: /my_email_status
+// Request Body: N/A
+// Response Body: `EmailStatusResponse`, or `NotFound` if there is no email recorded.
+#[utoipa::path(
+    tag = "email",
+    responses((status = 200, body = EmailStatusResponse)),
+)]
+#[get("/my_email_status")]
+async fn get_email_status(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::email::dsl::*;
+
+    let location = "my_email_status";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    match email
+        .filter(user.eq(user_id))
+        .select((email_record, verified_at))
+        .first::<(String, Option<DateTime<Utc>>)>(&mut conn)
+        .await
+        .optional()
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+    {
+        Some((record, verified)) => Ok(HttpResponse::Ok().json(EmailStatusResponse {
+            email: record,
+            verified: verified.is_some(),
+        })),
+        None => Err(APIError::NotFound),
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum EmailVerifyCodeResponse {
+    Sent,
+    AlreadyVerified,
+    // unix timestamp in seconds
+    TryAgainAfter(i64),
+}
+
+// [[API]]
+// desp: Send a code to the recorded email to verify it.
+// Method: POST
+// URL: /email_verify_code
+// Request Body: N/A
+// Response Body: `EmailVerifyCodeResponse`
+#[utoipa::path(
+    tag = "email",
+    responses((status = 200, body = EmailVerifyCodeResponse)),
+)]
+#[post("/email_verify_code")]
+async fn email_verify_code(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    mailer: web::Data<DynMailer>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::email::dsl::*;
+
+    let location = "email_verify_code";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let (address, verified, expires) = email
+        .filter(user.eq(user_id))
+        .select((email_record, verified_at, code_expires_at))
+        .first::<(String, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(&mut conn)
+        .await
+        .optional()
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+        .ok_or(APIError::NotFound)?;
+
+    if verified.is_some() {
+        return Ok(enum_json(EmailVerifyCodeResponse::AlreadyVerified));
+    }
+
+    let now = Utc::now();
+    if let Some(expires) = expires {
+        let resend_at = expires - TimeDelta::minutes(CODE_EXPIRE_MINUTES)
+            + TimeDelta::seconds(CODE_RESEND_SECONDS);
+        if resend_at > now {
+            return Ok(enum_json(EmailVerifyCodeResponse::TryAgainAfter(
+                resend_at.timestamp(),
+            )));
+        }
+    }
+
+    let code = cipher_util::gen_one_time_code();
+
+    diesel::update(email.filter(user.eq(user_id)))
+        .set((
+            code_hash.eq(cipher_util::hash_one_time_code(
+                &code,
+                &address,
+                &config().secrets.login_token,
+            )),
+            code_attempts.eq(0),
+            code_expires_at.eq(now + TimeDelta::minutes(CODE_EXPIRE_MINUTES)),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    send_mail(
+        &mailer,
+        Mail {
+            to: address,
+            subject: "元样 2025 邮箱验证码".to_string(),
+            body: format!("您的邮箱验证码是 {code}，{CODE_EXPIRE_MINUTES} 分钟内有效。"),
+        },
+        location,
+    )
+    .await?;
+
+    Ok(enum_json(EmailVerifyCodeResponse::Sent))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct EmailVerifyRequest {
+    pub code: String,
+}
+
+impl APIRequest for EmailVerifyRequest {
+    fn ok(&self) -> bool {
+        self.code.len() == 6
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum EmailVerifyResponse {
+    Verified,
+    InvalidCode,
+    // Request a new code.
+    Expired,
+}
+
+// [[API]]
+// desp: Verify the recorded email with the code from `/email_verify_code`.
+// Method: POST
+// URL: /email_verify
+// Request Body: `EmailVerifyRequest`
+// Response Body: `EmailVerifyResponse`
+#[utoipa::path(
+    tag = "email",
+    request_body = EmailVerifyRequest,
+    responses((status = 200, body = EmailVerifyResponse)),
+)]
+#[post("/email_verify")]
+async fn email_verify(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    form: web::Json<EmailVerifyRequest>,
+) -> Result<impl Responder, APIError> {
+    let location = "email_verify";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let result = conn
+        .transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                use crate::schema::email::dsl::*;
+
+                let record = email
+                    .filter(user.eq(user_id))
+                    .select((email_record, code_hash, code_attempts, code_expires_at))
+                    .for_update()
+                    .first::<(String, Option<String>, i32, Option<DateTime<Utc>>)>(conn)
+                    .await
+                    .optional()?;
+
+                let (address, expected_hash, tried, expires) = match record {
+                    Some((address, Some(expected_hash), tried, Some(expires))) => {
+                        (address, expected_hash, tried, expires)
+                    }
+                    Some(_) => return Ok(EmailVerifyResponse::Expired),
+                    None => return Err(APIError::NotFound),
+                };
+
+                if expires < Utc::now() || tried >= CODE_MAX_ATTEMPTS {
+                    return Ok(EmailVerifyResponse::Expired);
+                }
+
+                if cipher_util::hash_one_time_code(
+                    &form.code,
+                    &address,
+                    &config().secrets.login_token,
+                ) != expected_hash
+                {
+                    diesel::update(email.filter(user.eq(user_id)))
+                        .set(code_attempts.eq(tried + 1))
+                        .execute(conn)
+                        .await?;
+                    return Ok(EmailVerifyResponse::InvalidCode);
+                }
+
+                diesel::update(email.filter(user.eq(user_id)))
+                    .set((
+                        verified_at.eq(Some(Utc::now())),
+                        code_hash.eq::<Option<String>>(None),
+                        code_expires_at.eq::<Option<DateTime<Utc>>>(None),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(EmailVerifyResponse::Verified)
+            })
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    Ok(enum_json(result))
+}
@@ -1,6 +1,167 @@
+pub mod announcement;
 pub mod email;
 pub mod monitor;
 pub mod oracle;
+pub mod phase;
 pub mod puzzle;
+pub mod recovery;
 pub mod register;
+pub mod schedule;
 pub mod team;
+pub mod totp;
+
+use actix_web::middleware::from_fn;
+use actix_web::{get, web, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+use crate::util::api_util::ErrorBody;
+use crate::util::api_version::tag_enums;
+
+/// Registers the services listed in `$configure` and documents them in `$doc`,
+/// so no service is routed without a schema.
+/// CAVEAT: `configure` must register services through these lists only.
+macro_rules! services {
+    ($configure:ident, $doc:ident, [$($($segment:ident)::+),* $(,)?]) => {
+        fn $configure(cfg: &mut web::ServiceConfig) {
+            $(cfg.service($($segment)::+);)*
+        }
+
+        #[derive(OpenApi)]
+        #[openapi(paths($($($segment)::+),*))]
+        struct $doc;
+    };
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "yuanyang25 server",
+        description = "The paths and schemas of `/v1`, which the root paths alias. \
+            `/v2` is not documented here but for `/v2/unlock`: it serves the same paths, \
+            with its enum responses internally tagged by `type` (any content other than \
+            a struct goes to `value`)."
+    ),
+    // Every failed request answers with an `ErrorBody` when it accepts JSON.
+    components(schemas(ErrorBody))
+)]
+struct ApiInfo;
+
+/// The OpenAPI document of every service registered in `configure`, as served
+/// by `/v1`. The `/v2` variants are left out but for `/v2/unlock`.
+pub struct ApiDoc;
+
+impl OpenApi for ApiDoc {
+    fn openapi() -> utoipa::openapi::OpenApi {
+        let mut doc = ApiInfo::openapi();
+        doc.merge(SharedDoc::openapi());
+        doc.merge(V1Doc::openapi());
+        doc.merge(V2Doc::openapi());
+        doc.merge(UnversionedDoc::openapi());
+        doc
+    }
+}
+
+// [[API]]
+// desp: The OpenAPI document of this server.
+// Method: GET
+// URL: /openapi.json
+// Request Body: N/A
+// Response Body: the OpenAPI 3.1 document.
+#[utoipa::path(
+    tag = "meta",
+    responses((status = 200, description = "The OpenAPI document")),
+)]
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// The unversioned routes are the aliases of `/v1`, which the clients used
+/// before `/v2`. `/metrics` and `/openapi.json` are not versioned.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/v1").configure(v1))
+        .service(web::scope("/v2").wrap(from_fn(tag_enums)).configure(v2))
+        .configure(v1)
+        .configure(unversioned);
+}
+
+fn v1(cfg: &mut web::ServiceConfig) {
+    cfg.configure(shared).configure(v1_only);
+}
+
+/// Mutations take a JSON body, GETs a query string, and enums are internally
+/// tagged (see `api_version::internally_tagged`).
+fn v2(cfg: &mut web::ServiceConfig) {
+    cfg.configure(shared).configure(v2_only);
+}
+
+services!(
+    unversioned,
+    UnversionedDoc,
+    [monitor::export_metrics, openapi_json]
+);
+
+services!(v1_only, V1Doc, [puzzle::unlock]);
+
+services!(v2_only, V2Doc, [puzzle::unlock_json]);
+
+// Routes with the same request in every version.
+services!(
+    shared,
+    SharedDoc,
+    [
+        register::register_user,
+        register::get_user,
+        register::login_user,
+        register::logout,
+        register::set_locale,
+        team::create_team,
+        team::team_veri,
+        team::join_team,
+        team::create_team_invite,
+        team::team_invites,
+        team::revoke_team_invite,
+        team::exit_team,
+        team::info,
+        puzzle::decipher_key,
+        puzzle::submit_answer,
+        puzzle::staff_test_answer,
+        puzzle::staff_forgive_penalty,
+        puzzle::accessible_puzzles,
+        puzzle::my_progress,
+        puzzle::puzzle_status,
+        puzzle::staff_puzzle_stats,
+        puzzle::rank,
+        monitor::cache_size,
+        monitor::staff_login_locks,
+        monitor::staff_clear_login_lock,
+        monitor::rate_limit_status,
+        monitor::staff_activity,
+        oracle::create_oracle,
+        oracle::get_oracle,
+        oracle::check_oracle,
+        oracle::staff_list_oracle,
+        oracle::staff_reply_oracle,
+        oracle::staff_work_from,
+        email::get_email,
+        email::post_email,
+        email::email_signup_code,
+        email::email_register,
+        email::get_email_status,
+        email::email_verify_code,
+        email::email_verify,
+        announcement::staff_announce,
+        announcement::announcements,
+        totp::totp_status,
+        totp::totp_enroll,
+        totp::totp_confirm,
+        totp::totp_recovery_codes,
+        totp::totp_disable,
+        recovery::request_password_reset,
+        recovery::reset_password,
+        schedule::schedule,
+        schedule::staff_release,
+        phase::game_phase,
+        phase::staff_set_phase,
+    ]
+);
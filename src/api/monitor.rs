@@ -1,13 +1,34 @@
 use std::sync::Arc;
 
-use crate::util::cache::Cache;
+use crate::models::{PuzzleId, TeamId};
+use crate::util::attempt_limiter::{AttemptLimiter, LimitKey, LockStatus};
+use crate::util::cache::{Cache, CacheStatusResponse};
+use crate::util::cipher_util::check_bearer_token;
+use crate::util::config::config;
+use crate::util::metrics::{metrics, render_pool};
+use crate::util::rate_limit::{RateLimitStatusResponse, RateLimiter};
+use crate::util::stat::{ActivityBucket, ActivityFilter};
 
 use crate::util::api_util::*;
 use actix_session::Session;
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::DbPool;
+use crate::{DbPool, Ext};
 
+// [[API]]
+// desp: The (size, capacity) of each cache.
+// Method: GET
+// URL: /cache_size
+// Request Body: N/A
+// Response Body: `CacheStatusResponse`
+#[utoipa::path(
+    tag = "monitor",
+    responses((status = 200, body = CacheStatusResponse)),
+)]
 #[get("/cache_size")]
 async fn cache_size(
     mut session: Session,
@@ -18,3 +39,158 @@ async fn cache_size(
     get_team_id(&mut session, &pool, PRIVILEGE_ADMIN, location).await?;
     Ok(HttpResponse::Ok().json(cache.get_size()))
 }
+
+// [[API]]
+// desp: The rate limit config and the allowed and rejected requests by route.
+// Method: GET
+// URL: /rate_limit_status
+// Request Body: N/A
+// Response Body: `RateLimitStatusResponse`
+#[utoipa::path(
+    tag = "monitor",
+    responses((status = 200, body = RateLimitStatusResponse)),
+)]
+#[get("/rate_limit_status")]
+async fn rate_limit_status(
+    session: Session,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+) -> Result<impl Responder, APIError> {
+    user_privilege_check(&session, PRIVILEGE_ADMIN)?;
+    Ok(HttpResponse::Ok().json(rate_limiter.status()))
+}
+
+// [[API]]
+// desp: List users, teams and IPs with recent failed logins or team joins.
+// Method: GET
+// URL: /staff_login_locks
+// Request Body: N/A
+// Response Body: `Vec<LockStatus>`
+#[utoipa::path(
+    tag = "monitor",
+    responses((status = 200, body = Vec<LockStatus>)),
+)]
+#[get("/staff_login_locks")]
+async fn staff_login_locks(
+    session: Session,
+    limiter: web::Data<Arc<AttemptLimiter>>,
+) -> Result<impl Responder, APIError> {
+    user_privilege_check(&session, PRIVILEGE_STAFF)?;
+    Ok(HttpResponse::Ok().json(limiter.list()))
+}
+
+// [[API]]
+// desp: Clear the failed attempts and lock of a user, team or IP.
+// Method: POST
+// URL: /staff_clear_login_lock
+// Request Body: `LimitKey`, e.g. {"kind": "Login", "id": {"user": 1, "ip": "127.0.0.1"}}
+// Response Body: N/A
+#[utoipa::path(
+    tag = "monitor",
+    request_body = LimitKey,
+    responses((status = 200)),
+)]
+#[post("/staff_clear_login_lock")]
+async fn staff_clear_login_lock(
+    session: Session,
+    limiter: web::Data<Arc<AttemptLimiter>>,
+    form: web::Json<LimitKey>,
+) -> Result<impl Responder, APIError> {
+    user_privilege_check(&session, PRIVILEGE_STAFF)?;
+    limiter.clear(&form).await;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ActivityRequest {
+    granularity: Option<i64>, // minutes, 10 by default
+    puzzle_id: Option<PuzzleId>,
+    team_id: Option<TeamId>,
+}
+
+impl APIRequest for ActivityRequest {
+    fn ok(&self) -> bool {
+        self.granularity.is_none_or(|g| (1..=1440).contains(&g))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ActivityResponse {
+    granularity: i64, // minutes
+    updated: i64,     // unix timestamp in seconds
+    data: Vec<ActivityBucket>,
+}
+
+// [[API]]
+// desp: Submissions, wrong answers, unlock purchases, oracles and token flow per bucket, refreshed every 10 minutes.
+// Method: GET
+// URL: /staff_activity
+// Request Body: `ActivityRequest`, e.g. ?granularity=60&puzzle_id=1
+// Response Body: `ActivityResponse`
+#[utoipa::path(
+    tag = "monitor",
+    params(ActivityRequest),
+    responses((status = 200, body = ActivityResponse)),
+)]
+#[get("/staff_activity")]
+async fn staff_activity(
+    session: Session,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Query<ActivityRequest>,
+) -> Result<impl Responder, APIError> {
+    let location = "staff_activity";
+    form.sanity()?;
+    user_privilege_check(&session, PRIVILEGE_STAFF)?;
+
+    let granularity = form.granularity.unwrap_or(10);
+    let cacheddata = cache
+        .get_activity(ActivityFilter {
+            granularity: TimeDelta::minutes(granularity),
+            puzzle: form.puzzle_id,
+            team: form.team_id,
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    Ok(HttpResponse::Ok().json(ActivityResponse {
+        granularity,
+        updated: cacheddata.time.timestamp(),
+        data: cacheddata.data.clone(),
+    }))
+}
+
+// [[API]]
+// desp: Prometheus metrics of this instance, for admins or scrapers sending `Authorization: Bearer <METRICS_TOKEN>`.
+// Method: GET
+// URL: /metrics
+// Request Body: N/A
+// Response Body: the Prometheus text format.
+#[utoipa::path(
+    tag = "monitor",
+    responses((status = 200, description = "Prometheus text metrics", content_type = "text/plain")),
+)]
+#[get("/metrics")]
+async fn export_metrics(
+    req: HttpRequest,
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+) -> Result<impl Responder, APIError> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let scraper = matches!(
+        (bearer, &config().secrets.metrics_token),
+        (Some(given), Some(token)) if check_bearer_token(given, token)
+    );
+    if !scraper {
+        user_privilege_check(&session, PRIVILEGE_ADMIN)?;
+    }
+
+    let mut body = metrics().render();
+    render_pool(&pool, &mut body);
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
@@ -2,20 +2,25 @@ use std::sync::Arc;
 
 use actix_session::Session;
 use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::Utc;
 
 use diesel::prelude::*;
 
 use crate::models::*;
 use crate::util::economy::compulsory_team_balance;
+use crate::util::metrics::metrics;
+use crate::util::notify::Notifier;
 
 use diesel_async::{AsyncConnection, RunQueryDsl};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::util::api_util::{
     find_min_active_id, get_oracle_by_id, get_oracle_by_id_and_team,
     get_oracles_by_team_and_puzzle, get_oracles_from_id, update_active_oracle_and_return_team,
     user_privilege_check, PRIVILEGE_STAFF,
 };
+use crate::util::api_version::enum_json;
 use crate::{
     util::{
         api_util::{
@@ -28,7 +33,7 @@ use crate::{
     DbPool, Ext,
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct CreateOracleRequest {
     puzzle_id: i32,
     content: String,
@@ -42,7 +47,7 @@ impl APIRequest for CreateOracleRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum CreateOracleResponse {
     TooManyActiveOracle,
     Sucess {
@@ -52,6 +57,11 @@ enum CreateOracleResponse {
     }, // returns the id
 }
 
+#[utoipa::path(
+    tag = "oracle",
+    request_body = CreateOracleRequest,
+    responses((status = 200, body = CreateOracleResponse)),
+)]
 #[post("/create_oracle")]
 async fn create_oracle(
     pool: web::Data<Arc<DbPool>>,
@@ -63,6 +73,7 @@ async fn create_oracle(
     form.sanity()?;
 
     let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    cache.get_game_phase().await?.phase.check_counted()?;
     let puzzle_id = form.puzzle_id;
 
     let mut conn = pool
@@ -70,8 +81,11 @@ async fn create_oracle(
         .await
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
-    // check that the puzzle exist
-    cache.query_puzzle_cached(puzzle_id, |_| ()).await?;
+    // check that the puzzle exist and is open
+    cache
+        .query_puzzle_cached(puzzle_id, |puzzle| puzzle.base.availability(Utc::now()))
+        .await?
+        .check()?;
 
     let oracle_deposit = oracle_price();
 
@@ -123,11 +137,15 @@ async fn create_oracle(
         })
         .await
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
+    if let CreateOracleResponse::Sucess { cost, .. } = &result {
+        metrics().count_tokens(*cost);
+    }
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct GetOracleRequest {
     pub oracle_id: i32,
 }
@@ -140,6 +158,11 @@ impl APIRequest for GetOracleRequest {
 
 type GetOracleResponse = OracleRecord;
 
+#[utoipa::path(
+    tag = "oracle",
+    params(GetOracleRequest),
+    responses((status = 200, body = GetOracleResponse)),
+)]
 #[get("/get_oracle")]
 async fn get_oracle(
     pool: web::Data<Arc<DbPool>>,
@@ -159,7 +182,7 @@ async fn get_oracle(
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
     //如果是staff, 可以获取任何存在的oracle
-    //否则， 访问别的队伍的oracle会得到400
+    //否则， 访问别的队伍的oracle会得到404
     let result: Option<GetOracleResponse> =
         if user_privilege_check(&session, PRIVILEGE_STAFF).is_ok() {
             get_oracle_by_id(oracle_id, &mut conn).await?
@@ -170,11 +193,12 @@ async fn get_oracle(
     if let Some(record) = result {
         Ok(HttpResponse::Ok().json(record))
     } else {
-        Err(APIError::InvalidQuery)
+        Err(APIError::NotFound)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct CheckOracleRequest {
     pub puzzle_id: i32,
 }
@@ -185,12 +209,17 @@ impl APIRequest for CheckOracleRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct CheckOracleResponse {
     active: Vec<i32>,
     inactive: Vec<i32>,
 }
 
+#[utoipa::path(
+    tag = "oracle",
+    params(CheckOracleRequest),
+    responses((status = 200, body = CheckOracleResponse)),
+)]
 #[get("/check_oracle")]
 async fn check_oracle(
     pool: web::Data<Arc<DbPool>>,
@@ -202,11 +231,9 @@ async fn check_oracle(
     form.sanity()?;
 
     let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    cache.get_game_phase().await?.phase.check_started()?;
     let puzzle_id = form.puzzle_id;
 
-    // check that the puzzle exist
-    cache.query_puzzle_cached(puzzle_id, |_| ()).await?;
-
     let mut conn = pool
         .get()
         .await
@@ -229,7 +256,8 @@ async fn check_oracle(
     Ok(HttpResponse::Ok().json(CheckOracleResponse { active, inactive }))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct ListOracleRequest {
     pub start_oracle_id: i32,
     pub limit: usize,
@@ -241,11 +269,16 @@ impl APIRequest for ListOracleRequest {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct ListOracleResponse {
     oracles: Vec<OracleSummaryStaff>,
 }
 
+#[utoipa::path(
+    tag = "oracle",
+    params(ListOracleRequest),
+    responses((status = 200, body = ListOracleResponse)),
+)]
 #[get("/staff_list_oracle")]
 async fn staff_list_oracle(
     pool: web::Data<Arc<DbPool>>,
@@ -270,12 +303,16 @@ async fn staff_list_oracle(
     Ok(HttpResponse::Ok().json(ListOracleResponse { oracles }))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 enum WorkFromResponse {
     Start(i32),
     Nothing(&'static str),
 }
 
+#[utoipa::path(
+    tag = "oracle",
+    responses((status = 200, body = WorkFromResponse)),
+)]
 #[get("/staff_work_from")]
 async fn staff_work_from(
     pool: web::Data<Arc<DbPool>>,
@@ -297,10 +334,10 @@ async fn staff_work_from(
         _ => WorkFromResponse::Nothing("All clear!"),
     };
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct ReplyOracleRequest {
     pub oracle_id: i32,
     pub refund_amount: i64,
@@ -313,9 +350,15 @@ impl APIRequest for ReplyOracleRequest {
     }
 }
 
+#[utoipa::path(
+    tag = "oracle",
+    request_body = ReplyOracleRequest,
+    responses((status = 200)),
+)]
 #[post("/staff_reply_oracle")]
 async fn staff_reply_oracle(
     pool: web::Data<Arc<DbPool>>,
+    notifier: web::Data<Arc<Notifier>>,
     form: web::Json<ReplyOracleRequest>,
     session: Session,
 ) -> Result<impl Responder, APIError> {
@@ -331,28 +374,37 @@ async fn staff_reply_oracle(
 
     //如果refund超过了cost, 会被自动取min
     //如果尝试回复一个已经被回复过的，会400
-    conn.transaction::<_, APIError, _>(|conn| {
-        Box::pin(async move {
-            let (affected, amount) = update_active_oracle_and_return_team(
-                form.oracle_id,
-                form.refund_amount,
-                form.content.clone(),
-                conn,
-            )
-            .await?
-            .ok_or(APIError::InvalidQuery)?;
-            compulsory_team_balance(
-                affected,
-                amount,
-                format!("Refund for oracle {} by staff {}", form.oracle_id, staff_id).as_str(),
-                conn,
-            )
-            .await?;
-            Ok(())
+    let oracle_id = form.oracle_id;
+    let (affected, amount) = conn
+        .transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                let (affected, amount) = update_active_oracle_and_return_team(
+                    form.oracle_id,
+                    form.refund_amount,
+                    form.content.clone(),
+                    conn,
+                )
+                .await?
+                .ok_or(APIError::InvalidQuery)?;
+                compulsory_team_balance(
+                    affected,
+                    amount,
+                    format!("Refund for oracle {} by staff {}", form.oracle_id, staff_id).as_str(),
+                    conn,
+                )
+                .await?;
+                Ok((affected, amount))
+            })
         })
-    })
-    .await
-    .map_err(|e| e.set_location(location).tap(APIError::log))?;
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+    metrics().count_tokens(amount);
+
+    notifier.notify_team(
+        affected,
+        "元样 2025 神谕已回复".to_string(),
+        format!("您的队伍提交的神谕 #{oracle_id} 已被回复，请登录查看。"),
+    );
 
     Ok(HttpResponse::Ok())
 }
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::util::api_util::*;
+use crate::util::cache::Cache;
+use crate::util::game_phase::GamePhase;
+use crate::DbPool;
+
+#[derive(Debug, Serialize, ToSchema)]
+struct GamePhaseResponse {
+    phase: GamePhase,
+    since: i64, // unix timestamp in seconds
+}
+
+// [[API]]
+// desp: The current phase of the hunt.
+// Method: GET
+// URL: /game_phase
+// Request Body: N/A
+// Response Body: `GamePhaseResponse`
+#[utoipa::path(
+    tag = "phase",
+    responses((status = 200, body = GamePhaseResponse)),
+)]
+#[get("/game_phase")]
+async fn game_phase(cache: web::Data<Arc<Cache>>) -> Result<impl Responder, APIError> {
+    let state = cache.get_game_phase().await?;
+    Ok(HttpResponse::Ok().json(GamePhaseResponse {
+        phase: state.phase,
+        since: state.since.timestamp(),
+    }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetPhaseRequest {
+    phase: GamePhase,
+}
+
+// [[API]]
+// desp: Switch the hunt to another phase, e.g. freeze the scoreboard.
+// Method: POST
+// URL: /staff_set_phase
+// Request Body: `SetPhaseRequest`, e.g. {"phase": "Frozen"}
+// Response Body: N/A
+#[utoipa::path(
+    tag = "phase",
+    request_body = SetPhaseRequest,
+    responses((status = 200)),
+)]
+#[post("/staff_set_phase")]
+async fn staff_set_phase(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<SetPhaseRequest>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::game_phase::dsl::*;
+
+    let location = "staff_set_phase";
+    let (admin_id, _) = user_privilege_check(&session, PRIVILEGE_ADMIN)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    diesel::insert_into(game_phase)
+        .values((phase.eq(form.phase.to_db()), changed_by.eq(admin_id)))
+        .execute(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    // The statistics are cached by the freeze time, so a new phase picks its own.
+    cache.game_phase.invalidate(&()).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,15 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::models::{PuzzleBase, WaPenalty};
+use crate::models::{DecipherId, PuzzleBase, PuzzleId, TeamId, WaPenalty};
 
 use crate::util::api_util::*;
+use crate::util::api_version::enum_json;
 use crate::util::auto_fetch::Expiration;
 use crate::util::cache::Cache;
 use crate::util::cipher_util::cipher_chain;
 use crate::util::economy::{
     compulsory_team_balance, deciper_price, puzzle_reward, try_modify_team_balance,
+    WRONG_ANSWER_DESP,
 };
+use crate::util::game_phase::GamePhase;
+use crate::util::i18n::{t, Locale};
+use crate::util::metrics::metrics;
+use crate::util::penalty::PenaltySchedule;
+use crate::util::puzzle_graph::{fetch_solved, grant_free_unlocks};
+use crate::util::schedule::Availability;
+use crate::util::stat::{fetch_team_progress, PuzzleSolveStat};
 
 use actix_web::{get, post, web, HttpResponse, Responder};
 
@@ -19,6 +28,7 @@ use diesel::ExpressionMethods;
 use diesel_async::{AsyncConnection, RunQueryDsl};
 
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{DbPool, Ext};
 
@@ -27,6 +37,7 @@ pub struct Puzzle {
     pub base: PuzzleBase,
     pub answers: HashMap<String, i32>,
     pub other_answers: HashMap<String, (i32, String)>, //(other_answer_id, response)
+    pub penalty: PenaltySchedule,
 }
 
 pub enum CheckAnswerResult {
@@ -44,11 +55,13 @@ impl Puzzle {
         base: PuzzleBase,
         answers: Vec<(String, i32)>,
         other_answers: Vec<(String, (i32, String))>,
+        penalty: PenaltySchedule,
     ) -> Self {
         Self {
             base,
             answers: answers.into_iter().collect(),
             other_answers: other_answers.into_iter().collect(),
+            penalty,
         }
     }
     pub fn check(&self, submission: &str) -> CheckAnswerResult {
@@ -79,7 +92,8 @@ impl Puzzle {
 
 use actix_session::Session;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct DecipherKeyRequest {
     decipher_id: i32,
 }
@@ -90,7 +104,7 @@ impl APIRequest for DecipherKeyRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum DecipherKeyResponse {
     // returns the decipher key!.
     Full(String),
@@ -102,8 +116,13 @@ enum DecipherKeyResponse {
 // desp: Return the decipher_key of a puzzle
 // Method: GET
 // URL: /decipher_key
-// Request Body: `DecipherKeyRequest`
+// Request Query: `DecipherKeyRequest`
 // Response Body: `DecipherKeyResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    params(DecipherKeyRequest),
+    responses((status = 200, body = DecipherKeyResponse)),
+)]
 #[get("/decipher_key")]
 async fn decipher_key(
     pool: web::Data<Arc<DbPool>>,
@@ -114,20 +133,31 @@ async fn decipher_key(
     let location = "decipher_key";
     form.sanity()?;
     let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    cache.get_game_phase().await?.phase.check_started()?;
 
     let decipher_id = form.decipher_id;
     let answer = cache.decipher_cache.get(decipher_id).await?;
+    let unlocked = cache.unlock_cache.get((team_id, decipher_id)).await?;
 
-    let result = match cache.unlock_cache.get((team_id, decipher_id)).await? {
+    // Teams keep reading the keys they have after the window closes.
+    match (answer.availability(Utc::now()), unlocked) {
+        (Availability::NotYet(_), _) | (Availability::Closed(_), None) => {
+            return Err(APIError::NotAvailable)
+        }
+        _ => (),
+    }
+
+    let result = match unlocked {
         Some(0) => DecipherKeyResponse::Full(answer.get_key(0)),
         Some(i) => DecipherKeyResponse::Part(answer.get_key(i)),
         None => DecipherKeyResponse::Price(deciper_price(answer.pricing_type, answer.base_price)),
     };
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
 struct UnlockRequest {
     decipher_id: i32,
 }
@@ -138,7 +168,7 @@ impl APIRequest for UnlockRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum UnlockResponse {
     Success {
         key: String,
@@ -152,27 +182,81 @@ enum UnlockResponse {
 // desp: Pay to unlock
 // Method: POST
 // URL: /unlock
-// Request Body: `UnlockRequest`
+// Request Query: `UnlockRequest`
 // Response Body: `UnlockResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    params(UnlockRequest),
+    responses((status = 200, body = UnlockResponse)),
+)]
 #[post("/unlock")]
 async fn unlock(
     pool: web::Data<Arc<DbPool>>,
     cache: web::Data<Arc<Cache>>,
     form: web::Query<UnlockRequest>,
-    mut session: Session,
+    session: Session,
 ) -> Result<impl Responder, APIError> {
+    unlock_decipher(pool, cache, &form, session).await
+}
+
+// [[API]]
+// desp: Pay to unlock, `/v2` only.
+// Method: POST
+// URL: /v2/unlock
+// Request Body: `UnlockRequest`
+// Response Body: `UnlockResponse`
+#[utoipa::path(
+    path = "/v2/unlock",
+    tag = "puzzle",
+    request_body = UnlockRequest,
+    responses((status = 200, body = UnlockResponse)),
+)]
+#[post("/unlock")]
+async fn unlock_json(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<UnlockRequest>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    unlock_decipher(pool, cache, &form, session).await
+}
+
+async fn unlock_decipher(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: &UnlockRequest,
+    mut session: Session,
+) -> Result<HttpResponse, APIError> {
     let location = "unlock";
     form.sanity()?;
     let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    let phase = cache.get_game_phase().await?.phase;
+    phase.check_playable()?;
 
     let decipher_id = form.decipher_id;
     let answer = cache.decipher_cache.get(decipher_id).await?;
 
     if let Some(level) = cache.unlock_cache.get((team_id, decipher_id)).await? {
-        return Ok(HttpResponse::Ok().json(UnlockResponse::AlreadyUnlocked(answer.get_key(level))));
+        return Ok(enum_json(UnlockResponse::AlreadyUnlocked(
+            answer.get_key(level),
+        )));
     }
 
-    let price = deciper_price(answer.pricing_type, answer.base_price);
+    answer.availability(Utc::now()).check()?;
+
+    // Puzzles of unopened rounds and those unlocked by the graph are not for
+    // sale, while everything is free after the hunt.
+    let price = if phase == GamePhase::PostHunt {
+        0
+    } else if cache
+        .get_puzzle_graph()
+        .await?
+        .purchasable(decipher_id, Utc::now())
+    {
+        deciper_price(answer.pricing_type, answer.base_price)
+    } else {
+        return Err(APIError::NotAvailable);
+    };
     let level = (answer.depth - 1).max(0);
     let key = cipher_chain(&answer.root, level as usize);
 
@@ -210,6 +294,9 @@ async fn unlock(
         })
         .await
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
+    if let UnlockResponse::Success { price, .. } = &result {
+        metrics().count_tokens(-price);
+    }
 
     cache
         .unlock_cache
@@ -224,10 +311,10 @@ async fn unlock(
         )
         .await?;
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct SubmitAnswerRequest {
     puzzle_id: i32,
     answer: String,
@@ -239,7 +326,7 @@ impl APIRequest for SubmitAnswerRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum SubmitAnswerResponse {
     HasSubmitted(String),
     Success {
@@ -256,43 +343,78 @@ enum SubmitAnswerResponse {
         new_balance: i64,
     },
     PleaseToast(String),
+    /// The answer is checked but not recorded, after the hunt or the team
+    /// finished the puzzle.
+    Checked {
+        correct: bool,
+        key: Option<String>,
+        finish: bool,
+    },
 }
 
 // [[API]]
 // desp: Submit a puzzle
 // Method: POST
-// URL: /submit
+// URL: /submit_answer
 // Request Body: `SubmitAnswerRequest`
 // Response Body: `SubmitAnswerResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    request_body = SubmitAnswerRequest,
+    responses((status = 200, body = SubmitAnswerResponse)),
+)]
 #[post("/submit_answer")]
 async fn submit_answer(
     pool: web::Data<Arc<DbPool>>,
     cache: web::Data<Arc<Cache>>,
     form: web::Json<SubmitAnswerRequest>,
+    locale: Locale,
     mut session: Session,
 ) -> Result<impl Responder, APIError> {
     let location = "submit_answer";
     form.sanity()?;
     let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
     let is_staff = user_privilege_check(&session, PRIVILEGE_STAFF).is_ok();
+    let phase = cache.get_game_phase().await?.phase;
+    phase.check_playable()?;
 
     let puzzle_id = form.puzzle_id;
 
-    if let Some(wa_penalty_until) = cache.query_wa_penalty(team_id, puzzle_id).await? {
-        return Ok(HttpResponse::Ok().json(SubmitAnswerResponse::TryAgainAfter(
-            wa_penalty_until.timestamp(),
-        )));
-    }
-
-    let (check_result, decipher_id, mut is_meta) = cache
+    let (check_result, decipher_id, mut is_meta, availability, penalty_schedule) = cache
         .query_puzzle_cached(puzzle_id, |puzzle: &Puzzle| {
             (
                 puzzle.check(&form.answer),
                 puzzle.base.decipher,
                 puzzle.base.meta,
+                puzzle.base.availability(Utc::now()),
+                puzzle.penalty.clone(),
             )
         })
         .await?;
+    availability.check()?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    // After the hunt, or once the team has finished the puzzle, answers are
+    // only checked, without penalties.
+    if !phase.counted()
+        || check_solved(puzzle_id, team_id, &mut conn)
+            .await
+            .map_err(|e| e.set_location(location).tap(APIError::log))?
+    {
+        return Ok(enum_json(
+            check_only(&cache, check_result, decipher_id).await?,
+        ));
+    }
+
+    if let Some(wa_penalty_until) = cache.query_wa_penalty(team_id, puzzle_id).await? {
+        return Ok(enum_json(SubmitAnswerResponse::TryAgainAfter(
+            wa_penalty_until.timestamp(),
+        )));
+    }
 
     // Meta submitted by a staff is not counted in the rank.
     if is_staff {
@@ -305,10 +427,7 @@ async fn submit_answer(
         return Err(APIError::InvalidQuery);
     };
 
-    let mut conn = pool
-        .get()
-        .await
-        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+    let graph_cache = cache.clone();
 
     let result = conn
         .transaction::<_, APIError, _>(|conn| {
@@ -345,13 +464,15 @@ async fn submit_answer(
                             .await?;
                         if submission_result == 0 {
                             if level == 0 {
-                                return Ok(SubmitAnswerResponse::HasSubmitted(
-                                    "正确答案, 但已经提交过。".to_string(),
-                                ));
+                                return Ok(SubmitAnswerResponse::HasSubmitted(t(
+                                    locale,
+                                    "submit.has_submitted",
+                                )));
                             } else {
-                                return Ok(SubmitAnswerResponse::HasSubmitted(
-                                    "正确中间答案, 但已经提交过。".to_string(),
-                                ));
+                                return Ok(SubmitAnswerResponse::HasSubmitted(t(
+                                    locale,
+                                    "submit.has_submitted_mid",
+                                )));
                             }
                         }
 
@@ -373,8 +494,9 @@ async fn submit_answer(
 
                         if level < old_level {
                             let old_penalty = fetch_wa_cnt(puzzle_id, team_id, conn).await?;
-                            let new_penalty = old_penalty
-                                .map_or_else(WaPenalty::new, WaPenalty::on_new_mid_answer);
+                            let new_penalty = old_penalty.map_or_else(WaPenalty::new, |p| {
+                                p.on_new_mid_answer(&penalty_schedule)
+                            });
                             insert_or_update_wa_cnt(puzzle_id, team_id, new_penalty, conn).await?;
                         }
 
@@ -406,13 +528,12 @@ async fn submit_answer(
                         let mut penalty = fetch_wa_cnt(puzzle_id, team_id, conn)
                             .await?
                             .unwrap_or_else(WaPenalty::new);
-                        let fine = penalty.on_wrong_answer();
-                        assert!(fine >= 0);
+                        let fine = penalty.on_wrong_answer(&penalty_schedule);
 
                         let new_balance = compulsory_team_balance(
                             team_id,
                             -fine,
-                            format!("Wrong answer penalty puzzle {}", puzzle_id).as_str(),
+                            format!("{WRONG_ANSWER_DESP}{puzzle_id}").as_str(),
                             conn,
                         )
                         .await
@@ -434,30 +555,455 @@ async fn submit_answer(
         })
         .await
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
+    match &result {
+        SubmitAnswerResponse::Success { award_token, .. } => metrics().count_tokens(*award_token),
+        SubmitAnswerResponse::WrongAnswer { penalty_token, .. } => {
+            metrics().count_tokens(-penalty_token)
+        }
+        _ => {}
+    }
+
+    // The answer is accepted whatever happens to the unlocks it earns, which
+    // `/accessible_puzzles` grants as well.
+    if let SubmitAnswerResponse::Success { finish: true, .. } = result {
+        if let Err(e) = refresh_free_unlocks(&pool, &graph_cache, team_id, location).await {
+            e.set_location(location).log();
+        }
+    }
+
+    Ok(enum_json(result))
+}
+
+/// Reports an answer without touching submissions, balances or penalties.
+async fn check_only(
+    cache: &Cache,
+    check_result: CheckAnswerResult,
+    decipher_id: DecipherId,
+) -> Result<SubmitAnswerResponse, APIError> {
+    Ok(match check_result {
+        CheckAnswerResult::Toast((_, content)) => SubmitAnswerResponse::PleaseToast(content),
+        CheckAnswerResult::Accepted { level, .. } => SubmitAnswerResponse::Checked {
+            correct: true,
+            key: Some(cache.decipher_cache.get(decipher_id).await?.get_key(level)),
+            finish: level == 0,
+        },
+        CheckAnswerResult::WrongAnswer => SubmitAnswerResponse::Checked {
+            correct: false,
+            key: None,
+            finish: false,
+        },
+    })
+}
+
+/// Grants the free unlocks of the puzzle graph the team is entitled to, see
+/// `puzzle_graph::UnlockRule`.
+async fn refresh_free_unlocks(
+    pool: &DbPool,
+    cache: &Cache,
+    team_id: TeamId,
+    location: &'static str,
+) -> Result<(HashSet<PuzzleId>, Vec<DecipherId>), APIError> {
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+    let graph = cache.get_puzzle_graph().await?;
+    let solved = fetch_solved(&mut conn, team_id).await?;
+    let granted = grant_free_unlocks(cache, &graph, team_id, &solved).await?;
+    Ok((solved, granted))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum TestAnswerResponse {
+    Accepted {
+        level: i32,
+        total: i32,
+        reward_tokens: i64,
+    },
+    WrongAnswer,
+    PleaseToast(String),
+}
+
+// [[API]]
+// desp: Check an answer of any puzzle as a staff, without a team or any record.
+// Method: POST
+// URL: /staff_test_answer
+// Request Body: `SubmitAnswerRequest`
+// Response Body: `TestAnswerResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    request_body = SubmitAnswerRequest,
+    responses((status = 200, body = TestAnswerResponse)),
+)]
+#[post("/staff_test_answer")]
+async fn staff_test_answer(
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<SubmitAnswerRequest>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    form.sanity()?;
+    user_privilege_check(&session, PRIVILEGE_STAFF)?;
+
+    let result = cache
+        .query_puzzle_cached(form.puzzle_id, |puzzle: &Puzzle| {
+            match puzzle.check(&form.answer) {
+                CheckAnswerResult::Accepted {
+                    reward_tokens,
+                    level,
+                    total,
+                } => TestAnswerResponse::Accepted {
+                    level,
+                    total,
+                    reward_tokens,
+                },
+                CheckAnswerResult::WrongAnswer => TestAnswerResponse::WrongAnswer,
+                CheckAnswerResult::Toast((_, content)) => TestAnswerResponse::PleaseToast(content),
+            }
+        })
+        .await?;
+
+    Ok(enum_json(result))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ForgivePenaltyRequest {
+    team_id: i32,
+    puzzle_id: i32,
+}
+
+// [[API]]
+// desp: Forgive the wrong answer penalty of a team on a puzzle, resetting its levels and cooldown.
+// Method: POST
+// URL: /staff_forgive_penalty
+// Request Body: `ForgivePenaltyRequest`
+// Response Body: false if the team had no penalty on the puzzle.
+#[utoipa::path(
+    tag = "puzzle",
+    request_body = ForgivePenaltyRequest,
+    responses((status = 200, body = bool)),
+)]
+#[post("/staff_forgive_penalty")]
+async fn staff_forgive_penalty(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<ForgivePenaltyRequest>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::wrong_answer_cnt::dsl::*;
+
+    let location = "staff_forgive_penalty";
+    user_privilege_check(&session, PRIVILEGE_ADMIN)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    // The row is kept, its `wrong_attempts` are in the statistics.
+    let forgiven = WaPenalty::new();
+    let updated = diesel::update(
+        wrong_answer_cnt
+            .filter(team.eq(form.team_id))
+            .filter(puzzle.eq(form.puzzle_id)),
+    )
+    .set((
+        token_penalty_level.eq(forgiven.token_penalty_level),
+        time_penalty_level.eq(forgiven.time_penalty_level),
+        time_penalty_until.eq(forgiven.time_penalty_until),
+    ))
+    .execute(&mut conn)
+    .await
+    .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    // Other instances lift the cooldown once their cached one expires.
+    cache
+        .time_punish_cache
+        .invalidate((form.team_id, form.puzzle_id))
+        .await;
+
+    Ok(HttpResponse::Ok().json(updated > 0))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum PuzzleAccess {
+    Solved,
+    Unlocked,
+    Purchasable,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct AccessiblePuzzle {
+    puzzle_id: i32,
+    title: String,
+    round: Option<i32>,
+    decipher_id: i32,
+    access: PuzzleAccess,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RoundItem {
+    id: i32,
+    title: String,
+    opens_at: i64, // unix timestamp in seconds
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct AccessiblePuzzlesResponse {
+    rounds: Vec<RoundItem>,
+    puzzles: Vec<AccessiblePuzzle>,
+    // deciphers unlocked for free by this request
+    granted: Vec<i32>,
+}
+
+// [[API]]
+// desp: List the open rounds and the puzzles the team can access, granting the free unlocks it is entitled to
+//       unless the hunt has ended.
+// Method: GET
+// URL: /accessible_puzzles
+// Request Body: N/A
+// Response Body: `AccessiblePuzzlesResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    responses((status = 200, body = AccessiblePuzzlesResponse)),
+)]
+#[get("/accessible_puzzles")]
+async fn accessible_puzzles(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "accessible_puzzles";
+    let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    let phase = cache.get_game_phase().await?.phase;
+    phase.check_started()?;
+
+    // Nothing is unlocked, even for free, once the hunt has ended.
+    let (solved, granted) = if phase.check_playable().is_ok() {
+        refresh_free_unlocks(&pool, &cache, team_id, location)
+            .await
+            .map_err(|e| e.set_location(location).tap(APIError::log))?
+    } else {
+        let mut conn = pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+        let solved = fetch_solved(&mut conn, team_id)
+            .await
+            .map_err(|e| e.set_location(location).tap(APIError::log))?;
+        (solved, vec![])
+    };
+    let graph = cache.get_puzzle_graph().await?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+    let unlocked: HashSet<DecipherId> = {
+        use crate::schema::unlock::dsl::*;
+        use diesel::query_dsl::methods::SelectDsl;
+        unlock
+            .filter(team.eq(team_id))
+            .select(decipher)
+            .load::<i32>(&mut conn)
+            .await
+            .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+            .into_iter()
+            .collect()
+    };
+
+    let now = Utc::now();
+    let mut rounds: Vec<RoundItem> = graph
+        .rounds
+        .values()
+        .filter(|r| r.opens_at <= now)
+        .map(|r| RoundItem {
+            id: r.id,
+            title: r.title.clone(),
+            opens_at: r.opens_at.timestamp(),
+        })
+        .collect();
+    rounds.sort_by_key(|r| (r.opens_at, r.id));
+
+    let mut puzzles = vec![];
+    for node in graph
+        .puzzles
+        .iter()
+        .filter(|node| graph.round_open(node.round, now))
+    {
+        let access = if solved.contains(&node.puzzle_id) {
+            PuzzleAccess::Solved
+        } else if unlocked.contains(&node.decipher) {
+            PuzzleAccess::Unlocked
+        } else if graph.purchasable(node.decipher, now)
+            // `/unlock` only sells deciphers in their release window.
+            && cache.decipher_cache.get(node.decipher).await?.availability(now)
+                == Availability::Open
+        {
+            PuzzleAccess::Purchasable
+        } else {
+            continue;
+        };
+        puzzles.push(AccessiblePuzzle {
+            puzzle_id: node.puzzle_id,
+            title: node.title.clone(),
+            round: node.round,
+            decipher_id: node.decipher,
+            access,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(AccessiblePuzzlesResponse {
+        rounds,
+        puzzles,
+        granted,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SubmissionItem {
+    depth: i32, // 0 for the final answer
+    reward: i64,
+    time: i64, // unix timestamp in seconds
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ToastItem {
+    content: String,
+    time: i64, // unix timestamp in seconds
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PuzzleProgress {
+    puzzle_id: i32,
+    title: String,
+    decipher_id: i32,
+    unlock_level: Option<i32>, // None if not unlocked
+    solved: bool,
+    submissions: Vec<SubmissionItem>,
+    penalty_until: Option<i64>, // unix timestamp in seconds
+    toasts: Vec<ToastItem>,
+    oracles: usize,
+    active_oracles: usize,
+}
+
+// [[API]]
+// desp: The progress of the team on every released puzzle of the open rounds, or that it has worked on.
+// Method: GET
+// URL: /my_progress
+// Request Body: N/A
+// Response Body: `Vec<PuzzleProgress>`
+#[utoipa::path(
+    tag = "puzzle",
+    responses((status = 200, body = Vec<PuzzleProgress>)),
+)]
+#[get("/my_progress")]
+async fn my_progress(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "my_progress";
+    let team_id = get_team_id(&mut session, &pool, PRIVILEGE_MINIMAL, location).await?;
+    cache.get_game_phase().await?.phase.check_started()?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+    let progress = fetch_team_progress(&mut conn, team_id)
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+    let graph = cache.get_puzzle_graph().await?;
+
+    let now = Utc::now();
+    let mut result: Vec<PuzzleProgress> = vec![];
+    for node in &graph.puzzles {
+        let worked_on = progress.unlocks.contains_key(&node.decipher)
+            || progress.submissions.contains_key(&node.puzzle_id);
+        // The titles of unreleased puzzles are not told.
+        if !worked_on {
+            if !graph.round_open(node.round, now) {
+                continue;
+            }
+            let availability = cache
+                .query_puzzle_cached(node.puzzle_id, |puzzle| puzzle.base.availability(now))
+                .await?;
+            if let Availability::NotYet(_) = availability {
+                continue;
+            }
+        }
+
+        let submissions = progress
+            .submissions
+            .get(&node.puzzle_id)
+            .cloned()
+            .unwrap_or_default();
+        let (oracles, active_oracles) = progress
+            .oracles
+            .get(&node.puzzle_id)
+            .copied()
+            .unwrap_or_default();
+        result.push(PuzzleProgress {
+            puzzle_id: node.puzzle_id,
+            title: node.title.clone(),
+            decipher_id: node.decipher,
+            unlock_level: progress.unlocks.get(&node.decipher).copied(),
+            solved: submissions.iter().any(|(depth, _, _)| *depth == 0),
+            submissions: submissions
+                .into_iter()
+                .map(|(depth, reward, time)| SubmissionItem {
+                    depth,
+                    reward,
+                    time: time.timestamp(),
+                })
+                .collect(),
+            penalty_until: progress
+                .penalties
+                .get(&node.puzzle_id)
+                .map(|t| t.timestamp()),
+            toasts: progress
+                .toasts
+                .get(&node.puzzle_id)
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(content, time)| ToastItem {
+                    content,
+                    time: time.timestamp(),
+                })
+                .collect(),
+            oracles,
+            active_oracles,
+        });
+    }
 
     Ok(HttpResponse::Ok().json(result))
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub enum PuzzleStatus {
     Passed,
     Unlocked,
     Locked,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct PuzzleStatusItem {
     puzzle_id: i32,
     passed: usize,
     unlocked: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct PuzzleStatusResponse {
     updated: i64, // unix timestamp in seconds
     data: Vec<PuzzleStatusItem>,
 }
 
+#[utoipa::path(
+    tag = "puzzle",
+    responses((status = 200, body = PuzzleStatusResponse)),
+)]
 #[get("/puzzle_status")]
 async fn puzzle_status(cache: web::Data<Arc<Cache>>) -> Result<impl Responder, APIError> {
     let location = "puzzle_status";
@@ -480,12 +1026,50 @@ async fn puzzle_status(cache: web::Data<Arc<Cache>>) -> Result<impl Responder, A
     }))
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize, ToSchema)]
+struct SolveStatResponse {
+    updated: i64, // unix timestamp in seconds
+    data: Vec<PuzzleSolveStat>,
+}
+
+// [[API]]
+// desp: Solve statistics of every puzzle for the authors, refreshed every 10 minutes.
+// Method: GET
+// URL: /staff_puzzle_stats
+// Request Body: N/A
+// Response Body: `SolveStatResponse`
+#[utoipa::path(
+    tag = "puzzle",
+    responses((status = 200, body = SolveStatResponse)),
+)]
+#[get("/staff_puzzle_stats")]
+async fn staff_puzzle_stats(
+    cache: web::Data<Arc<Cache>>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "staff_puzzle_stats";
+    user_privilege_check(&session, PRIVILEGE_STAFF)?;
+    let cacheddata = cache
+        .get_solve_stat()
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    Ok(HttpResponse::Ok().json(SolveStatResponse {
+        updated: cacheddata.time.timestamp(),
+        data: cacheddata.data.clone(),
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 enum RankResponse {
     Success { rank_record: i32, time: i64 },
     NotFound,
 }
 
+#[utoipa::path(
+    tag = "puzzle",
+    responses((status = 200, body = RankResponse)),
+)]
 #[get("/rank")]
 async fn rank(
     mut session: Session,
@@ -518,5 +1102,5 @@ async fn rank(
         Err(e) => Err(log_server_error(e, location, ERROR_DB_UNKNOWN)),
     }?;
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
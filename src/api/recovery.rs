@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use actix_session::Session;
+use actix_web::{post, web, Responder};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::email::{is_valid_email, normalize_email};
+use crate::schema::users;
+use crate::util::api_util::*;
+use crate::util::api_version::enum_json;
+use crate::util::cache::Cache;
+use crate::util::cipher_util;
+use crate::util::config::config;
+use crate::util::mailer::{send_mail, DynMailer, Mail};
+use crate::{DbPool, Ext};
+
+const RESET_TOKEN_EXPIRE_MINUTES: i64 = 30;
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct PasswordResetRequest {
+    userid: Option<i32>,
+    email: Option<String>,
+}
+
+impl APIRequest for PasswordResetRequest {
+    fn ok(&self) -> bool {
+        match (&self.userid, &self.email) {
+            (Some(userid), None) => *userid >= 0,
+            (None, Some(email)) => is_valid_email(email),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum PasswordResetResponse {
+    // Returned whether or not the account exists, so accounts cannot be enumerated.
+    Requested,
+}
+
+// [[API]]
+// desp: Mail a single-use reset token to the verified email of a user.
+//       Exactly one of `userid` and `email` should be given. An address
+//       verified by several users gets nothing, give the `userid` instead.
+// Method: POST
+// URL: /request_password_reset
+// Request Body: `PasswordResetRequest`
+// Response Body: `PasswordResetResponse`
+#[utoipa::path(
+    tag = "recovery",
+    request_body = PasswordResetRequest,
+    responses((status = 200, body = PasswordResetResponse)),
+)]
+#[post("/request_password_reset")]
+async fn request_password_reset(
+    pool: web::Data<Arc<DbPool>>,
+    mailer: web::Data<DynMailer>,
+    form: web::Json<PasswordResetRequest>,
+) -> Result<impl Responder, APIError> {
+    use crate::schema::email::dsl as email_dsl;
+    use crate::schema::password_reset::dsl as reset_dsl;
+
+    let location = "request_password_reset";
+    form.sanity()?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    // Only mail verified addresses, which may still be shared by several users.
+    let query = email_dsl::email
+        .filter(email_dsl::verified_at.is_not_null())
+        .select((email_dsl::user, email_dsl::email_record))
+        .into_boxed();
+    let query = match (&form.userid, &form.email) {
+        (Some(userid), _) => query.filter(email_dsl::user.eq(*userid)),
+        (_, Some(address)) => {
+            query.filter(lower(email_dsl::email_record).eq(normalize_email(address)))
+        }
+        _ => return Err(APIError::InvalidFormData),
+    };
+
+    let mut targets = query
+        .order(email_dsl::user)
+        .limit(2)
+        .load::<(i32, String)>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    // The answer is the same either way, not to tell which accounts exist.
+    let (user_id, address) = match (targets.pop(), targets.pop()) {
+        (Some(target), None) => target,
+        (None, _) => {
+            info!("Password reset requested for unknown account {form:?}");
+            return Ok(enum_json(PasswordResetResponse::Requested));
+        }
+        (Some(_), Some(_)) => {
+            info!("Password reset refused for an address of several users {form:?}");
+            return Ok(enum_json(PasswordResetResponse::Requested));
+        }
+    };
+
+    let reset_token = hex::encode(cipher_util::get_salt::<32>());
+
+    diesel::insert_into(reset_dsl::password_reset)
+        .values((
+            reset_dsl::user.eq(user_id),
+            reset_dsl::token_hash.eq(cipher_util::hash_reset_token(
+                &reset_token,
+                &config().secrets.login_token,
+            )),
+            reset_dsl::expires_at.eq(Utc::now() + TimeDelta::minutes(RESET_TOKEN_EXPIRE_MINUTES)),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    send_mail(
+        &mailer,
+        Mail {
+            to: address,
+            subject: "元样 2025 重置密码".to_string(),
+            body: format!(
+                "用户 {user_id} 的密码重置令牌是\r\n\r\n{reset_token}\r\n\r\n{RESET_TOKEN_EXPIRE_MINUTES} 分钟内有效，仅可使用一次。如非本人操作，请忽略此邮件。"
+            ),
+        },
+        location,
+    )
+    .await?;
+
+    Ok(enum_json(PasswordResetResponse::Requested))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ResetPasswordRequest {
+    token: String,
+    // SHA256 of the password.
+    password: String,
+}
+
+impl APIRequest for ResetPasswordRequest {
+    fn ok(&self) -> bool {
+        self.token.len() == 64 && self.password.len() == 64
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum ResetPasswordResponse {
+    // returns the user id. All sessions of the user are logged out.
+    Success(i32),
+    // Unknown, expired or already used.
+    InvalidToken,
+}
+
+// [[API]]
+// desp: Set a new password with a token from `/request_password_reset`.
+// Method: POST
+// URL: /reset_password
+// Request Body: `ResetPasswordRequest`
+// Response Body: `ResetPasswordResponse`
+#[utoipa::path(
+    tag = "recovery",
+    request_body = ResetPasswordRequest,
+    responses((status = 200, body = ResetPasswordResponse)),
+)]
+#[post("/reset_password")]
+async fn reset_password(
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<ResetPasswordRequest>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "reset_password";
+    form.sanity()?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let result = conn
+        .transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                use crate::schema::password_reset::dsl as reset_dsl;
+
+                let hashed =
+                    cipher_util::hash_reset_token(&form.token, &config().secrets.login_token);
+                let record = reset_dsl::password_reset
+                    .filter(reset_dsl::token_hash.eq(&hashed))
+                    .filter(reset_dsl::used.eq(false))
+                    .select((reset_dsl::user, reset_dsl::expires_at))
+                    .for_update()
+                    .first::<(i32, DateTime<Utc>)>(conn)
+                    .await
+                    .optional()?;
+
+                let user_id = match record {
+                    Some((user_id, expires)) if expires > Utc::now() => user_id,
+                    _ => return Ok(ResetPasswordResponse::InvalidToken),
+                };
+
+                // Every outstanding token of the user is consumed.
+                diesel::update(reset_dsl::password_reset.filter(reset_dsl::user.eq(user_id)))
+                    .set(reset_dsl::used.eq(true))
+                    .execute(conn)
+                    .await?;
+
+                let password = form.password.clone();
+                let (salt, salted_password, scheme) = web::block(move || {
+                    cipher_util::gen_salted_password(&password, &config().secrets.login_token)
+                })
+                .await
+                .map_err(|e| log_server_error(e, location, ERROR_BLOCKING))?;
+
+                diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set((
+                        users::salt.eq(&salt),
+                        users::password.eq(&salted_password),
+                        users::password_scheme.eq(scheme),
+                        users::session_version.eq(users::session_version + 1),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(ResetPasswordResponse::Success(user_id))
+            })
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    if let ResetPasswordResponse::Success(user_id) = result {
+        cache.session_version_cache.invalidate(user_id).await;
+        session.clear();
+    }
+
+    Ok(enum_json(result))
+}
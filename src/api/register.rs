@@ -1,26 +1,34 @@
 use crate::schema::users;
 use crate::util::api_util::*;
+use crate::util::api_version::enum_json;
+use crate::util::config::config;
 use crate::VERICODE_LENGTH;
 
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 
-use actix_web::{get, post, web, HttpResponse, Responder};
-use dotenv::dotenv;
-use once_cell::sync::Lazy;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::ops::DerefMut;
 use std::sync::Arc;
+use utoipa::ToSchema;
 
+use crate::api::totp::check_second_factor;
 use crate::models::User;
-use crate::util::cipher_util::DecodeTokenError;
-use crate::{schema, util::cipher_util, DbPool};
+use crate::util::attempt_limiter::{AttemptLimiter, LimitKey};
+use crate::util::cache::Cache;
+use crate::util::cipher_util::{DecodeTokenError, DecodeTokenErrorResponse};
+use crate::util::i18n::Locale;
+use crate::{schema, util::cipher_util, DbPool, Ext};
 
 use actix_session::Session;
 
-use crate::util::api_util::{ERROR_DB_UNKNOWN, SESSION_PRIVILEGE, SESSION_USER_ID};
+use crate::util::api_util::{
+    ERROR_DB_UNKNOWN, SESSION_LOCALE, SESSION_PRIVILEGE, SESSION_USER_ID, SESSION_VERSION,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct RegisterRequest {
     // Max 100.
     username: String,
@@ -35,21 +43,27 @@ impl APIRequest for RegisterRequest {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum RegisterResponse {
     // returns the user id.
     Success(i32),
+    // The password is updated, but the user enabled the second factor,
+    // so log in with `/login` instead. Returns the user id.
+    SecondFactorRequired(i32),
     // json that describes the failure.
-    Failed(DecodeTokenError),
+    Failed(DecodeTokenErrorResponse),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct LoginRequest {
     userid: i32,
     auth: AuthMethod,
+    // TOTP or recovery code, required if the user enabled the second factor.
+    #[serde(default)]
+    second_factor: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "method", content = "data")]
 enum AuthMethod {
     Password(String),
@@ -58,56 +72,96 @@ enum AuthMethod {
 
 impl APIRequest for LoginRequest {
     fn ok(&self) -> bool {
-        match &self.auth {
+        (match &self.auth {
             AuthMethod::Password(pw) => pw.len() == 64,
             AuthMethod::Totp(veri) => veri.len() == VERICODE_LENGTH,
-        }
+        }) && self
+            .second_factor
+            .as_ref()
+            .is_none_or(|code| code.len() <= 16)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 enum LoginResponse {
     //Returns the user id
     Success(i32),
     Error,
+    // Retry with `second_factor`.
+    SecondFactorRequired,
+    // unix timestamp in seconds
+    TryAgainAfter(i64),
 }
 
-static LOGIN_TOKEN: Lazy<String> = Lazy::new(|| {
-    dotenv().ok();
-    env::var("LOGIN_TOKEN").expect("Environment variable LOGIN_TOKEN not set")
-});
-
-static REGISTER_TOKEN: Lazy<String> = Lazy::new(|| {
-    dotenv().ok();
-    env::var("REGISTER_TOKEN").expect("Environment variable REGISTER_TOKEN not set")
-});
-
-fn set_loggedin_session(
+pub(crate) fn set_loggedin_session(
     session: &mut Session,
-    id: i32,
-    privilege: i32,
+    user: &User,
     location: &'static str,
 ) -> Result<(), APIError> {
     session
-        .insert(SESSION_USER_ID, id)
+        .insert(SESSION_USER_ID, user.id)
         .map_err(|e| log_server_error(e, location, ERROR_SESSION_INSERT))?;
     session
-        .insert(SESSION_PRIVILEGE, privilege)
+        .insert(SESSION_PRIVILEGE, user.privilege)
         .map_err(|e| log_server_error(e, location, ERROR_SESSION_INSERT))?;
+    session
+        .insert(SESSION_VERSION, user.session_version)
+        .map_err(|e| log_server_error(e, location, ERROR_SESSION_INSERT))?;
+    if let Some(locale) = &user.locale {
+        session
+            .insert(SESSION_LOCALE, locale)
+            .map_err(|e| log_server_error(e, location, ERROR_SESSION_INSERT))?;
+    }
     Ok(())
 }
 
+// Upgrades a verified password to the current KDF. Failures are only logged,
+// the user is still logged in with the old hash.
+async fn rehash_password<C>(user_id: i32, password: String, conn: &mut C)
+where
+    C: DerefMut<Target = AsyncPgConnection> + Send,
+{
+    let location = "rehash_password";
+
+    let hashed = web::block(move || {
+        cipher_util::gen_salted_password(&password, &config().secrets.login_token)
+    })
+    .await
+    .map_err(|e| log_server_error(e, location, ERROR_BLOCKING));
+
+    if let Ok((salt, salted_password, scheme)) = hashed {
+        diesel::update(users::table.filter(users::id.eq(user_id)))
+            .set((
+                users::salt.eq(&salt),
+                users::password.eq(&salted_password),
+                users::password_scheme.eq(scheme),
+            ))
+            .execute(conn)
+            .await
+            .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))
+            .ok();
+    }
+}
+
 // [[API]]
-// desp: Register or update password with token from wechat.
+// desp: Register or update password with token from wechat. Does not log in
+//       if the user enabled the second factor.
 // Method: Post
 // URL: /register
 // Request Body: `RegisterRequest`
 // Response Body: `RegisterResponse`
 //
+#[utoipa::path(
+    tag = "register",
+    request_body = RegisterRequest,
+    responses((status = 200, body = RegisterResponse)),
+)]
 #[post("/register")]
 pub async fn register_user(
     pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
     form: web::Json<RegisterRequest>,
+    locale: Locale,
     mut session: Session,
 ) -> Result<impl Responder, APIError> {
     use schema::users;
@@ -119,39 +173,89 @@ pub async fn register_user(
         .await
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
-    let response = match cipher_util::decode_token(form.token.as_str(), REGISTER_TOKEN.as_str()) {
+    let token_hash = cipher_util::hash_register_token(&form.token);
+
+    let response = match cipher_util::decode_token(form.token.as_str(), &config().register_token) {
+        Ok(_) if cache.consumed_token.contains_key(&token_hash) => {
+            RegisterResponse::Failed(DecodeTokenError::Reused.localized(locale))
+        }
         Ok((_version, mark, openid)) => {
-            let (salt, salted_password) =
-                cipher_util::gen_salted_password(&form.password, &LOGIN_TOKEN);
-
-            let user: User = diesel::insert_into(users::table)
-                .values((
-                    users::username.eq(&form.username),
-                    users::openid.eq(openid.as_str()),
-                    users::privilege.eq(mark as i32),
-                    users::salt.eq(&salt),
-                    users::password.eq(&salted_password),
-                ))
-                .on_conflict(users::openid)
-                .do_update()
-                .set((
-                    users::username.eq(&form.username),
-                    users::privilege.eq(mark as i32),
-                    users::salt.eq(&salt),
-                    users::password.eq(&salted_password),
-                ))
-                .returning(User::as_returning())
-                .get_result(&mut conn)
+            let password = form.password.clone();
+            let (salt, salted_password, scheme) = web::block(move || {
+                cipher_util::gen_salted_password(&password, &config().secrets.login_token)
+            })
+            .await
+            .map_err(|e| log_server_error(e, location, ERROR_BLOCKING))?;
+
+            let consumed_hash = token_hash.clone();
+            let username = form.username.clone();
+            // Consumed tokens are kept for at least a day after any of them expires.
+            let forget_before = Utc::now()
+                - TimeDelta::minutes(config().register_token.expire_minutes as i64)
+                - TimeDelta::days(1);
+
+            let registered = conn
+                .transaction::<_, APIError, _>(|conn| {
+                    Box::pin(async move {
+                        use schema::consumed_token::dsl::*;
+
+                        let consumed = diesel::insert_into(consumed_token)
+                            .values(token_hash.eq(&consumed_hash))
+                            .on_conflict_do_nothing()
+                            .execute(conn)
+                            .await?;
+                        if consumed == 0 {
+                            return Ok(None);
+                        }
+
+                        diesel::delete(consumed_token.filter(consumed_at.lt(forget_before)))
+                            .execute(conn)
+                            .await?;
+
+                        let user: User = diesel::insert_into(users::table)
+                            .values((
+                                users::username.eq(&username),
+                                users::openid.eq(openid.as_str()),
+                                users::privilege.eq(mark as i32),
+                                users::salt.eq(&salt),
+                                users::password.eq(&salted_password),
+                                users::password_scheme.eq(scheme),
+                            ))
+                            .on_conflict(users::openid)
+                            .do_update()
+                            .set((
+                                users::username.eq(&username),
+                                users::privilege.eq(mark as i32),
+                                users::salt.eq(&salt),
+                                users::password.eq(&salted_password),
+                                users::password_scheme.eq(scheme),
+                            ))
+                            .returning(User::as_returning())
+                            .get_result(conn)
+                            .await?;
+                        Ok(Some(user))
+                    })
+                })
                 .await
-                .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+                .map_err(|e| e.set_location(location).tap(APIError::log))?;
 
-            session.clear();
-            set_loggedin_session(&mut session, user.id, user.privilege, "register")?;
-            RegisterResponse::Success(user.id)
+            cache.consumed_token.insert(token_hash, ()).await;
+
+            match registered {
+                Some(user) if user.totp_enabled_at.is_some() => {
+                    RegisterResponse::SecondFactorRequired(user.id)
+                }
+                Some(user) => {
+                    session.clear();
+                    set_loggedin_session(&mut session, &user, "register")?;
+                    RegisterResponse::Success(user.id)
+                }
+                None => RegisterResponse::Failed(DecodeTokenError::Reused.localized(locale)),
+            }
         }
-        Err(err) => RegisterResponse::Failed(err),
+        Err(err) => RegisterResponse::Failed(err.localized(locale)),
     };
-    Ok(HttpResponse::Ok().json(response))
+    Ok(enum_json(response))
 }
 
 // [[API]]
@@ -161,56 +265,165 @@ pub async fn register_user(
 // Request Body: `LoginRequest`
 // Response Body: `LoginResponse`
 //
+#[utoipa::path(
+    tag = "register",
+    request_body = LoginRequest,
+    responses((status = 200, body = LoginResponse)),
+)]
 #[post("/login")]
 async fn login_user(
     pool: web::Data<Arc<DbPool>>,
+    limiter: web::Data<Arc<AttemptLimiter>>,
     form: web::Json<LoginRequest>,
+    req: HttpRequest,
     mut session: Session,
 ) -> Result<impl Responder, APIError> {
     let location = "login";
     form.sanity()?;
+
+    let id = form.userid;
+    let ip = client_ip(&req);
+    let limit_keys = [
+        LimitKey::Login {
+            user: id,
+            ip: ip.clone(),
+        },
+        LimitKey::Ip(ip.clone()),
+    ];
+    let second_factor_keys = [LimitKey::SecondFactor(id), LimitKey::Ip(ip)];
+
+    if let Some(locked_until) = limiter.check(&limit_keys).await {
+        return Ok(enum_json(LoginResponse::TryAgainAfter(
+            locked_until.timestamp(),
+        )));
+    }
+
     let mut conn = pool
         .get()
         .await
         .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
 
-    let id = form.userid;
-
     let result: LoginResponse = if let Ok(user) = users::table
         .filter(users::id.eq(id))
         .get_result::<User>(&mut conn)
         .await
     {
-        match &form.auth {
+        let checked = match &form.auth {
             AuthMethod::Password(pw) => {
-                if let Some(user) =
-                    cipher_util::check_salted_password(&user, pw.as_str(), &LOGIN_TOKEN)
-                {
-                    session.clear();
-                    set_loggedin_session(&mut session, user.id, user.privilege, "login_password")?;
+                let checked = {
+                    let user = user.clone();
+                    let pw = pw.clone();
+                    web::block(move || {
+                        cipher_util::check_salted_password(
+                            &user,
+                            pw.as_str(),
+                            &config().secrets.login_token,
+                        )
+                        .is_some()
+                    })
+                    .await
+                    .map_err(|e| log_server_error(e, location, ERROR_BLOCKING))?
+                };
 
-                    LoginResponse::Success(user.id)
-                } else {
-                    LoginResponse::Error
+                if checked && cipher_util::password_needs_rehash(&user) {
+                    rehash_password(user.id, pw.clone(), &mut conn).await;
                 }
+                checked
             }
-            AuthMethod::Totp(veri) => {
-                if cipher_util::verify_totp(user.openid.as_str(), veri.as_str()) {
-                    session.clear();
-                    set_loggedin_session(&mut session, user.id, user.privilege, "login_totp")?;
-                    LoginResponse::Success(id)
-                } else {
-                    LoginResponse::Error
+            AuthMethod::Totp(veri) => cipher_util::verify_totp(user.openid.as_str(), veri.as_str()),
+        };
+
+        if !checked {
+            limiter.on_failure(&limit_keys).await;
+            LoginResponse::Error
+        } else if user.totp_enabled_at.is_some() {
+            limiter.on_success(&limit_keys[0]).await;
+            match &form.second_factor {
+                None => LoginResponse::SecondFactorRequired,
+                Some(code) => {
+                    if let Some(locked_until) = limiter.check(&second_factor_keys).await {
+                        LoginResponse::TryAgainAfter(locked_until.timestamp())
+                    } else if check_second_factor(&user, code, &mut conn, location).await? {
+                        limiter.on_success(&second_factor_keys[0]).await;
+                        session.clear();
+                        set_loggedin_session(&mut session, &user, "login_second_factor")?;
+                        LoginResponse::Success(user.id)
+                    } else {
+                        limiter.on_failure(&second_factor_keys).await;
+                        LoginResponse::Error
+                    }
                 }
             }
+        } else {
+            limiter.on_success(&limit_keys[0]).await;
+            session.clear();
+            set_loggedin_session(&mut session, &user, "login")?;
+            LoginResponse::Success(user.id)
         }
     } else {
+        limiter.on_failure(&limit_keys).await;
         LoginResponse::Error
     };
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SetLocaleRequest {
+    // e.g. "zh-CN" or "en", or null to follow `Accept-Language`.
+    locale: Option<Locale>,
+}
+
+// [[API]]
+// desp: Set the preferred language of messages.
+// Method: POST
+// URL: /set_locale
+// Request Body: `SetLocaleRequest`
+// Response Body: N/A
+#[utoipa::path(
+    tag = "register",
+    request_body = SetLocaleRequest,
+    responses((status = 200)),
+)]
+#[post("/set_locale")]
+async fn set_locale(
+    pool: web::Data<Arc<DbPool>>,
+    form: web::Json<SetLocaleRequest>,
+    session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "set_locale";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let tag = form.locale.map(|locale| locale.tag());
+
+    diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::locale.eq(tag))
+        .execute(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    match tag {
+        Some(tag) => session
+            .insert(SESSION_LOCALE, tag)
+            .map_err(|e| log_server_error(e, location, ERROR_SESSION_INSERT))?,
+        None => {
+            session.remove(SESSION_LOCALE);
+        }
+    }
+
+    Ok(HttpResponse::Ok())
 }
 
+#[utoipa::path(
+    tag = "register",
+    responses((status = 200)),
+)]
 #[get("/logout")]
 async fn logout(session: Session) -> Result<impl Responder, APIError> {
     session.clear();
@@ -218,6 +431,10 @@ async fn logout(session: Session) -> Result<impl Responder, APIError> {
 }
 
 // For debug only!
+#[utoipa::path(
+    tag = "register",
+    responses((status = 200, body = String, content_type = "text/plain")),
+)]
 #[get("/user")]
 async fn get_user(session: Session) -> impl Responder {
     if let (Ok(Some(user_id)), Ok(Some(user_privilege))) = (
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::{DecipherId, PuzzleId};
+use crate::util::api_util::*;
+use crate::util::cache::Cache;
+use crate::DbPool;
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ScheduleItem {
+    id: i32,
+    available_from: Option<i64>,  // unix timestamp in seconds
+    available_until: Option<i64>, // unix timestamp in seconds
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ScheduleResponse {
+    puzzles: Vec<ScheduleItem>,
+    deciphers: Vec<ScheduleItem>,
+}
+
+type Window = (i32, Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+fn schedule_items(windows: Vec<Window>) -> Vec<ScheduleItem> {
+    windows
+        .into_iter()
+        .map(|(id, from, until)| ScheduleItem {
+            id,
+            available_from: from.map(|t| t.timestamp()),
+            available_until: until.map(|t| t.timestamp()),
+        })
+        .collect()
+}
+
+// [[API]]
+// desp: List the puzzles and deciphers with a release window. The others are always available.
+// Method: GET
+// URL: /schedule
+// Request Body: N/A
+// Response Body: `ScheduleResponse`
+#[utoipa::path(
+    tag = "schedule",
+    responses((status = 200, body = ScheduleResponse)),
+)]
+#[get("/schedule")]
+async fn schedule(pool: web::Data<Arc<DbPool>>) -> Result<impl Responder, APIError> {
+    use crate::schema::decipher::dsl as decipher_dsl;
+    use crate::schema::puzzle::dsl as puzzle_dsl;
+
+    let location = "schedule";
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let puzzles = puzzle_dsl::puzzle
+        .filter(
+            puzzle_dsl::available_from
+                .is_not_null()
+                .or(puzzle_dsl::available_until.is_not_null()),
+        )
+        .order(puzzle_dsl::id)
+        .select((
+            puzzle_dsl::id,
+            puzzle_dsl::available_from,
+            puzzle_dsl::available_until,
+        ))
+        .load::<Window>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    let deciphers = decipher_dsl::decipher
+        .filter(
+            decipher_dsl::available_from
+                .is_not_null()
+                .or(decipher_dsl::available_until.is_not_null()),
+        )
+        .order(decipher_dsl::id)
+        .select((
+            decipher_dsl::id,
+            decipher_dsl::available_from,
+            decipher_dsl::available_until,
+        ))
+        .load::<Window>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    Ok(HttpResponse::Ok().json(ScheduleResponse {
+        puzzles: schedule_items(puzzles),
+        deciphers: schedule_items(deciphers),
+    }))
+}
+
+/// What `/staff_release` releases.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "kind", content = "id")]
+enum ReleaseTarget {
+    Puzzle(PuzzleId),
+    Decipher(DecipherId),
+}
+
+// [[API]]
+// desp: Release a puzzle or decipher now, moving its `available_from` earlier.
+// Method: POST
+// URL: /staff_release
+// Request Body: `ReleaseTarget`, e.g. {"kind": "Puzzle", "id": 1}
+// Response Body: false if it was released already or does not exist.
+#[utoipa::path(
+    tag = "schedule",
+    request_body = ReleaseTarget,
+    responses((status = 200, body = bool)),
+)]
+#[post("/staff_release")]
+async fn staff_release(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    cache: web::Data<Arc<Cache>>,
+    form: web::Json<ReleaseTarget>,
+) -> Result<impl Responder, APIError> {
+    let location = "staff_release";
+    user_privilege_check(&session, PRIVILEGE_ADMIN)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+    let now = Utc::now();
+
+    // Other instances notice the release once their cached copy expires.
+    let released = match *form {
+        ReleaseTarget::Puzzle(puzzle_id) => {
+            use crate::schema::puzzle::dsl::*;
+            let updated = diesel::update(puzzle)
+                .filter(id.eq(puzzle_id))
+                .filter(available_from.gt(now))
+                .set(available_from.eq(now))
+                .execute(&mut conn)
+                .await
+                .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+            cache.puzzle_cache.invalidate(puzzle_id).await;
+            updated > 0
+        }
+        ReleaseTarget::Decipher(decipher_id) => {
+            use crate::schema::decipher::dsl::*;
+            let updated = diesel::update(decipher)
+                .filter(id.eq(decipher_id))
+                .filter(available_from.gt(now))
+                .set(available_from.eq(now))
+                .execute(&mut conn)
+                .await
+                .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+            cache.decipher_cache.invalidate(decipher_id).await;
+            updated > 0
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(released))
+}
@@ -1,21 +1,29 @@
 use std::sync::Arc;
 
-use crate::schema::{team, users};
-use crate::util::{api_util::*, cipher_util};
+use crate::schema::{team, team_invite, users};
+use crate::util::attempt_limiter::{AttemptLimiter, LimitKey};
+use crate::util::{api_util::*, api_version::enum_json, cipher_util, config::config};
 
-use actix_web::{get, post, web, HttpResponse, Responder};
-use diesel::{ExpressionMethods, QueryDsl};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::AsyncConnection;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use log::warn;
 use serde::{Deserialize, Serialize};
+use std::ops::DerefMut;
+use utoipa::ToSchema;
 
 use crate::models::Team;
 use crate::{DbPool, Ext, VERICODE_LENGTH};
 
 use actix_session::Session;
 
-#[derive(Debug, Serialize)]
+// 16 random bytes in hexadecimal digits.
+const INVITE_TOKEN_LENGTH: usize = 32;
+const INVITE_MAX_LIFETIME_MINUTES: i64 = 7 * 24 * 60;
+
+#[derive(Debug, Serialize, ToSchema)]
 enum CreateTeamResponse {
     Success { id: i32 },
     AlreadyInTeam { id: i32 },
@@ -23,10 +31,14 @@ enum CreateTeamResponse {
 
 // [[API]]
 // desp: Create a team.
-// Method: GET
+// Method: POST
 // URL: /create_team
 // Request Body: N/A
 // Response Body: `CreateTeamResponse`
+#[utoipa::path(
+    tag = "team",
+    responses((status = 200, body = CreateTeamResponse)),
+)]
 #[post("/create_team")]
 async fn create_team(
     pool: web::Data<Arc<DbPool>>,
@@ -64,6 +76,7 @@ async fn create_team(
                         .values((
                             team_dsl::size.eq(1),
                             team_dsl::salt.eq(hex::encode(cipher_util::get_salt::<32>())),
+                            team_dsl::captain.eq(Some(user_id)),
                         )) // 32 * 8 = 256 Bits salt encoded into 64 hexdecimal digits
                         .get_result::<Team>(conn)
                         .await
@@ -87,10 +100,10 @@ async fn create_team(
         .map(handle_session(&mut session))
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Serialize, PartialEq, ToSchema)]
 enum TeamTOTPResponse {
     Success { id: i32, totp: String },
     NotInTeam,
@@ -102,6 +115,10 @@ enum TeamTOTPResponse {
 // URL: /team_veri
 // Request Body: N/A
 // Response Body: `TeamTOTPResponse`
+#[utoipa::path(
+    tag = "team",
+    responses((status = 200, body = TeamTOTPResponse)),
+)]
 #[get("/team_veri")]
 async fn team_veri(
     pool: web::Data<Arc<DbPool>>,
@@ -150,44 +167,107 @@ async fn team_veri(
         return Err(APIError::NotInTeam);
     }
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct JoinTeamRequest {
     team_id: i32,
-    vericode: String,
+    // Exactly one of the vericode from `/team_veri` and an invite token.
+    #[serde(default)]
+    vericode: Option<String>,
+    #[serde(default)]
+    invite: Option<String>,
 }
 
 impl APIRequest for JoinTeamRequest {
     fn ok(&self) -> bool {
-        self.team_id >= 0 && self.vericode.len() == VERICODE_LENGTH
+        self.team_id >= 0
+            && match (&self.vericode, &self.invite) {
+                (Some(vericode), None) => vericode.len() == VERICODE_LENGTH,
+                (None, Some(invite)) => invite.len() == INVITE_TOKEN_LENGTH,
+                _ => false,
+            }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Consumes one use of a valid invite to the team, returns false if there is none.
+async fn use_team_invite<C>(
+    team_id: i32,
+    invite_token: &str,
+    conn: &mut C,
+) -> Result<bool, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + Send,
+{
+    use crate::schema::team_invite::dsl::*;
+
+    Ok(diesel::update(
+        team_invite
+            .filter(team.eq(team_id))
+            .filter(token_hash.eq(cipher_util::hash_invite_token(
+                invite_token,
+                &config().secrets.login_token,
+            )))
+            .filter(revoked.eq(false))
+            .filter(expires_at.gt(Utc::now()))
+            .filter(uses.lt(max_uses)),
+    )
+    .set(uses.eq(uses + 1))
+    .execute(conn)
+    .await?
+        == 1)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 enum JoinTeamResponse {
     Success { id: i32 },
     AlreadyInTeam,
     TeamFull,
     AuthError,
+    // unix timestamp in seconds
+    TryAgainAfter(i64),
 }
 
 // [[API]]
-// desp: Return the verification code for joinning in a team.
+// desp: Join a team with its verification code or an invite.
 // Method: POST
 // URL: /join_team
-// Request Body: N/A
-// Response Body: `TeamTOTPResponse`
+// Request Body: `JoinTeamRequest`
+// Response Body: `JoinTeamResponse`
+#[utoipa::path(
+    tag = "team",
+    request_body = JoinTeamRequest,
+    responses((status = 200, body = JoinTeamResponse)),
+)]
 #[post("/join_team")]
 async fn join_team(
     pool: web::Data<Arc<DbPool>>,
+    limiter: web::Data<Arc<AttemptLimiter>>,
     form: web::Json<JoinTeamRequest>,
+    req: HttpRequest,
     mut session: Session,
 ) -> Result<impl Responder, APIError> {
     let location = "join_team";
     form.sanity()?;
     let (user_id, user_priv) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let ip = client_ip(&req);
+    let limit_keys = [
+        LimitKey::User(user_id),
+        LimitKey::Team {
+            team: form.team_id,
+            ip: ip.clone(),
+        },
+        LimitKey::Ip(ip),
+    ];
+
+    if let Some(locked_until) = limiter.check(&limit_keys).await {
+        return Ok(enum_json(JoinTeamResponse::TryAgainAfter(
+            locked_until.timestamp(),
+        )));
+    }
+
     let mut conn = pool
         .get()
         .await
@@ -214,7 +294,13 @@ async fn join_team(
                         } else if team.is_staff && user_priv < PRIVILEGE_STAFF {
                             warn!("user priv {user_priv} too low to join a staff team");
                             Ok((JoinTeamResponse::AuthError, kill_session))
-                        } else if cipher_util::verify_totp(team.salt.as_str(), &form.vericode) {
+                        } else if match (&form.vericode, &form.invite) {
+                            (Some(vericode), _) => {
+                                cipher_util::verify_totp(team.salt.as_str(), vericode)
+                            }
+                            (None, Some(invite)) => use_team_invite(team_id, invite, conn).await?,
+                            (None, None) => false,
+                        } {
                             // Update the user's team reference
                             diesel::update(users::table.filter(users::id.eq(user_id)))
                                 .set(users::team.eq(Some(team_id)))
@@ -222,9 +308,13 @@ async fn join_team(
                                 .await
                                 .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
 
-                            // Update the team's size
+                            // Update the team's size, the first to join a team
+                            // left without a captain takes over.
                             diesel::update(team::table.filter(team::id.eq(team_id)))
-                                .set(team::size.eq(team.size + 1))
+                                .set((
+                                    team::size.eq(team.size + 1),
+                                    team::captain.eq(team.captain.or(Some(user_id))),
+                                ))
                                 .execute(conn)
                                 .await
                                 .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
@@ -242,10 +332,14 @@ async fn join_team(
         .map(handle_session(&mut session))
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
 
-    Ok(HttpResponse::Ok().json(result))
+    if let JoinTeamResponse::AuthError = result {
+        limiter.on_failure(&limit_keys).await;
+    }
+
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 enum ExitTeamResponse {
     Success { id: i32 },
     NotInTeam,
@@ -253,11 +347,15 @@ enum ExitTeamResponse {
 }
 
 // [[API]]
-// desp: Return the verification code for joinning in a team.
+// desp: Leave the current team.
 // Method: POST
 // URL: /exit_team
 // Request Body: N/A
 // Response Body: `ExitTeamResponse`
+#[utoipa::path(
+    tag = "team",
+    responses((status = 200, body = ExitTeamResponse)),
+)]
 #[post("/exit_team")]
 async fn exit_team(
     pool: web::Data<Arc<DbPool>>,
@@ -301,6 +399,21 @@ async fn exit_team(
                             .await
                             .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
 
+                        // The member who joined the earliest takes over as the captain
+                        if team.captain == Some(user_id) {
+                            let successor = users::table
+                                .filter(users::team.eq(team.id))
+                                .select(diesel::dsl::min(users::id))
+                                .first::<Option<i32>>(conn)
+                                .await
+                                .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+                            diesel::update(team::table.filter(team::id.eq(team.id)))
+                                .set(team::captain.eq(successor))
+                                .execute(conn)
+                                .await
+                                .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+                        }
+
                         Ok((ExitTeamResponse::Success { id: team.id }, kill_session))
                     }
                     Some(_) => Ok((ExitTeamResponse::NotAllowed, kill_session)),
@@ -312,10 +425,10 @@ async fn exit_team(
         .map(handle_session(&mut session))
         .map_err(|e| e.set_location(location).tap(APIError::log))?;
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(enum_json(result))
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct InfoResponse {
     user_id: i32,
     privilege: i32,
@@ -326,9 +439,13 @@ struct InfoResponse {
 // [[API]]
 // desp: get basic info
 // Method: GET
-// URL: /exit_team
+// URL: /info
 // Request Body: N/A
 // Response Body: `InfoResponse`
+#[utoipa::path(
+    tag = "team",
+    responses((status = 200, body = InfoResponse)),
+)]
 #[get("/info")]
 async fn info(
     pool: web::Data<Arc<DbPool>>,
@@ -363,3 +480,233 @@ async fn info(
         token_balance,
     }))
 }
+
+// Returns the team of the user, who must be its captain.
+async fn captain_team<C>(user_id: i32, conn: &mut C) -> Result<Team, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + Send,
+{
+    let user = fetch_user_from_id(user_id, conn)
+        .await?
+        .ok_or(APIError::InvalidSession)?;
+    let team = match user.team {
+        Some(team_id) => fetch_team_from_id(team_id, conn).await?,
+        None => None,
+    }
+    .ok_or(APIError::NotInTeam)?;
+
+    if team.captain != Some(user_id) {
+        return Err(APIError::Unauthorized);
+    }
+    Ok(team)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateInviteRequest {
+    lifetime_minutes: i64,
+    max_uses: i32,
+}
+
+impl APIRequest for CreateInviteRequest {
+    fn ok(&self) -> bool {
+        (1..=INVITE_MAX_LIFETIME_MINUTES).contains(&self.lifetime_minutes) && self.max_uses >= 1
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CreateInviteResponse {
+    id: i32,
+    // Shown only once, share it with `team_id` to `/join_team`.
+    token: String,
+    expires_at: i64, // unix timestamp in seconds
+}
+
+// [[API]]
+// desp: Create an invite to the team of the captain. The uses are capped by
+//       the max size of the team anyway.
+// Method: POST
+// URL: /create_team_invite
+// Request Body: `CreateInviteRequest`
+// Response Body: `CreateInviteResponse`
+#[utoipa::path(
+    tag = "team",
+    request_body = CreateInviteRequest,
+    responses((status = 200, body = CreateInviteResponse)),
+)]
+#[post("/create_team_invite")]
+async fn create_team_invite(
+    pool: web::Data<Arc<DbPool>>,
+    form: web::Json<CreateInviteRequest>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "create_team_invite";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let invite_token = hex::encode(cipher_util::get_salt::<16>());
+    let expires_at = Utc::now() + TimeDelta::minutes(form.lifetime_minutes);
+
+    let team = captain_team(user_id, &mut conn)
+        .await
+        .inspect_err(kill_session(&mut session))
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    let id = diesel::insert_into(team_invite::table)
+        .values((
+            team_invite::team.eq(team.id),
+            team_invite::token_hash.eq(cipher_util::hash_invite_token(
+                &invite_token,
+                &config().secrets.login_token,
+            )),
+            team_invite::created_by.eq(user_id),
+            team_invite::expires_at.eq(expires_at),
+            team_invite::max_uses.eq(form.max_uses.min(team.max_size)),
+        ))
+        .returning(team_invite::id)
+        .get_result::<i32>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    Ok(HttpResponse::Ok().json(CreateInviteResponse {
+        id,
+        token: invite_token,
+        expires_at: expires_at.timestamp(),
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct InviteResponse {
+    id: i32,
+    created_by: i32,
+    created_at: i64, // unix timestamp in seconds
+    expires_at: i64, // unix timestamp in seconds
+    max_uses: i32,
+    uses: i32,
+    revoked: bool,
+}
+
+// [[API]]
+// desp: List the invites of the team of the captain, newest first.
+// Method: GET
+// URL: /team_invites
+// Request Body: N/A
+// Response Body: `Vec<InviteResponse>`
+#[utoipa::path(
+    tag = "team",
+    responses((status = 200, body = Vec<InviteResponse>)),
+)]
+#[get("/team_invites")]
+async fn team_invites(
+    pool: web::Data<Arc<DbPool>>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "team_invites";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let team = captain_team(user_id, &mut conn)
+        .await
+        .inspect_err(kill_session(&mut session))
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    let invites: Vec<InviteResponse> = team_invite::table
+        .filter(team_invite::team.eq(team.id))
+        .order(team_invite::id.desc())
+        .select((
+            team_invite::id,
+            team_invite::created_by,
+            team_invite::created_at,
+            team_invite::expires_at,
+            team_invite::max_uses,
+            team_invite::uses,
+            team_invite::revoked,
+        ))
+        .load::<(i32, i32, DateTime<Utc>, DateTime<Utc>, i32, i32, bool)>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .map(
+            |(id, created_by, created_at, expires_at, max_uses, uses, revoked)| InviteResponse {
+                id,
+                created_by,
+                created_at: created_at.timestamp(),
+                expires_at: expires_at.timestamp(),
+                max_uses,
+                uses,
+                revoked,
+            },
+        )
+        .collect();
+
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct RevokeInviteRequest {
+    invite_id: i32,
+}
+
+impl APIRequest for RevokeInviteRequest {
+    fn ok(&self) -> bool {
+        self.invite_id >= 0
+    }
+}
+
+// [[API]]
+// desp: Revoke an invite of the team of the captain.
+// Method: POST
+// URL: /revoke_team_invite
+// Request Body: `RevokeInviteRequest`
+// Response Body: N/A, or `NotFound` if the team has no such invite.
+#[utoipa::path(
+    tag = "team",
+    request_body = RevokeInviteRequest,
+    responses((status = 200)),
+)]
+#[post("/revoke_team_invite")]
+async fn revoke_team_invite(
+    pool: web::Data<Arc<DbPool>>,
+    form: web::Json<RevokeInviteRequest>,
+    mut session: Session,
+) -> Result<impl Responder, APIError> {
+    let location = "revoke_team_invite";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let team = captain_team(user_id, &mut conn)
+        .await
+        .inspect_err(kill_session(&mut session))
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    diesel::update(
+        team_invite::table
+            .filter(team_invite::id.eq(form.invite_id))
+            .filter(team_invite::team.eq(team.id)),
+    )
+    .set(team_invite::revoked.eq(true))
+    .returning(team_invite::id)
+    .get_result::<i32>(&mut conn)
+    .await
+    .optional()
+    .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?
+    .ok_or(APIError::NotFound)?;
+
+    Ok(HttpResponse::Ok())
+}
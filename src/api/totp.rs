@@ -0,0 +1,444 @@
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::User;
+use crate::schema::{totp_recovery_code, users};
+use crate::util::api_util::*;
+use crate::util::api_version::enum_json;
+use crate::util::attempt_limiter::{AttemptLimiter, LimitKey};
+use crate::util::cipher_util;
+use crate::util::config::config;
+use crate::{DbPool, Ext};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Checks a TOTP or an unused recovery code of a user with the second factor
+/// enabled. Both are consumed on success.
+pub(crate) async fn check_second_factor<C>(
+    user: &User,
+    code: &str,
+    conn: &mut C,
+    location: &'static str,
+) -> Result<bool, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + Send,
+{
+    let secret = match (&user.totp_secret, user.totp_enabled_at) {
+        (Some(secret), Some(_)) => secret,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = cipher_util::verify_rfc6238(secret, code, user.totp_last_step) {
+        // Concurrent logins with the same code race here, only one wins.
+        let updated = diesel::update(
+            users::table
+                .filter(users::id.eq(user.id))
+                .filter(users::totp_last_step.lt(step)),
+        )
+        .set(users::totp_last_step.eq(step))
+        .execute(conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+        return Ok(updated == 1);
+    }
+
+    let used = diesel::update(
+        totp_recovery_code::table
+            .filter(totp_recovery_code::user.eq(user.id))
+            .filter(
+                totp_recovery_code::code_hash.eq(cipher_util::hash_recovery_code(
+                    code,
+                    &config().secrets.login_token,
+                )),
+            )
+            .filter(totp_recovery_code::used_at.is_null()),
+    )
+    .set(totp_recovery_code::used_at.eq(Some(Utc::now())))
+    .execute(conn)
+    .await
+    .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+    Ok(used == 1)
+}
+
+// Replaces all recovery codes of the user, returns the new codes in plain text.
+async fn regenerate_recovery_codes(
+    user_id: i32,
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<String>, APIError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| cipher_util::gen_recovery_code())
+        .collect();
+
+    diesel::delete(totp_recovery_code::table.filter(totp_recovery_code::user.eq(user_id)))
+        .execute(conn)
+        .await?;
+
+    diesel::insert_into(totp_recovery_code::table)
+        .values(
+            codes
+                .iter()
+                .map(|code| {
+                    (
+                        totp_recovery_code::user.eq(user_id),
+                        totp_recovery_code::code_hash.eq(cipher_util::hash_recovery_code(
+                            code,
+                            &config().secrets.login_token,
+                        )),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(conn)
+        .await?;
+
+    Ok(codes)
+}
+
+async fn load_user<C>(user_id: i32, conn: &mut C, location: &'static str) -> Result<User, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + Send,
+{
+    users::table
+        .filter(users::id.eq(user_id))
+        .get_result::<User>(conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct TotpCodeRequest {
+    // TOTP, or a recovery code where noted.
+    code: String,
+}
+
+impl APIRequest for TotpCodeRequest {
+    fn ok(&self) -> bool {
+        self.code.len() <= 16
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct TotpStatusResponse {
+    enabled: bool,
+    recovery_codes_left: i64,
+}
+
+// [[API]]
+// desp: Whether the second factor is enabled.
+// Method: GET
+// URL: /totp_status
+// Request Body: N/A
+// Response Body: `TotpStatusResponse`
+#[utoipa::path(
+    tag = "totp",
+    responses((status = 200, body = TotpStatusResponse)),
+)]
+#[get("/totp_status")]
+async fn totp_status(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+) -> Result<impl Responder, APIError> {
+    let location = "totp_status";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let user = load_user(user_id, &mut conn, location).await?;
+
+    let recovery_codes_left = totp_recovery_code::table
+        .filter(totp_recovery_code::user.eq(user_id))
+        .filter(totp_recovery_code::used_at.is_null())
+        .count()
+        .get_result::<i64>(&mut conn)
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    Ok(HttpResponse::Ok().json(TotpStatusResponse {
+        enabled: user.totp_enabled_at.is_some(),
+        recovery_codes_left,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum TotpEnrollResponse {
+    // Add the secret to an authenticator app (e.g. scan the uri as a QR code),
+    // then confirm with `/totp_confirm`.
+    Pending { secret: String, uri: String },
+    AlreadyEnabled,
+}
+
+// [[API]]
+// desp: Start enrolling an RFC 6238 authenticator as the second factor.
+//       Enrolling again before confirming replaces the secret.
+// Method: POST
+// URL: /totp_enroll
+// Request Body: N/A
+// Response Body: `TotpEnrollResponse`
+#[utoipa::path(
+    tag = "totp",
+    responses((status = 200, body = TotpEnrollResponse)),
+)]
+#[post("/totp_enroll")]
+async fn totp_enroll(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+) -> Result<impl Responder, APIError> {
+    let location = "totp_enroll";
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let secret = cipher_util::gen_totp_secret();
+
+    let pending = diesel::update(
+        users::table
+            .filter(users::id.eq(user_id))
+            .filter(users::totp_enabled_at.is_null()),
+    )
+    .set((
+        users::totp_secret.eq(Some(&secret)),
+        users::totp_last_step.eq(0),
+    ))
+    .returning(users::username)
+    .get_result::<String>(&mut conn)
+    .await
+    .optional()
+    .map_err(|e| log_server_error(e, location, ERROR_DB_UNKNOWN))?;
+
+    let response = match pending {
+        Some(username) => TotpEnrollResponse::Pending {
+            uri: cipher_util::totp_uri(&secret, &format!("{username} ({user_id})")),
+            secret,
+        },
+        None => TotpEnrollResponse::AlreadyEnabled,
+    };
+    Ok(enum_json(response))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+enum TotpCodeResponse {
+    // Recovery codes, shown only once.
+    Enabled(Vec<String>),
+    Regenerated(Vec<String>),
+    Disabled,
+    InvalidCode,
+    NotEnrolled,
+    // unix timestamp in seconds
+    TryAgainAfter(i64),
+}
+
+// [[API]]
+// desp: Confirm the enrolment with a TOTP from the authenticator.
+// Method: POST
+// URL: /totp_confirm
+// Request Body: `TotpCodeRequest`
+// Response Body: `TotpCodeResponse`, one of Enabled, InvalidCode, NotEnrolled, TryAgainAfter
+#[utoipa::path(
+    tag = "totp",
+    request_body = TotpCodeRequest,
+    responses((status = 200, body = TotpCodeResponse)),
+)]
+#[post("/totp_confirm")]
+async fn totp_confirm(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    limiter: web::Data<Arc<AttemptLimiter>>,
+    form: web::Json<TotpCodeRequest>,
+) -> Result<impl Responder, APIError> {
+    let location = "totp_confirm";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+    let limit_keys = [LimitKey::SecondFactor(user_id)];
+    if let Some(locked_until) = limiter.check(&limit_keys).await {
+        return Ok(enum_json(TotpCodeResponse::TryAgainAfter(
+            locked_until.timestamp(),
+        )));
+    }
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let result = conn
+        .transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                let (secret, enabled_at, last_step) = users::table
+                    .filter(users::id.eq(user_id))
+                    .select((
+                        users::totp_secret,
+                        users::totp_enabled_at,
+                        users::totp_last_step,
+                    ))
+                    .for_update()
+                    .first::<(Option<String>, Option<DateTime<Utc>>, i64)>(conn)
+                    .await?;
+
+                let secret = match (secret, enabled_at) {
+                    (Some(secret), None) => secret,
+                    _ => return Ok(TotpCodeResponse::NotEnrolled),
+                };
+
+                let step = match cipher_util::verify_rfc6238(&secret, &form.code, last_step) {
+                    Some(step) => step,
+                    None => return Ok(TotpCodeResponse::InvalidCode),
+                };
+
+                diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set((
+                        users::totp_enabled_at.eq(Some(Utc::now())),
+                        users::totp_last_step.eq(step),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(TotpCodeResponse::Enabled(
+                    regenerate_recovery_codes(user_id, conn).await?,
+                ))
+            })
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+
+    if let TotpCodeResponse::InvalidCode = result {
+        limiter.on_failure(&limit_keys).await;
+    }
+    Ok(enum_json(result))
+}
+
+// [[API]]
+// desp: Replace the recovery codes, authorized by a TOTP or a recovery code.
+// Method: POST
+// URL: /totp_recovery_codes
+// Request Body: `TotpCodeRequest`
+// Response Body: `TotpCodeResponse`, one of Regenerated, InvalidCode, NotEnrolled, TryAgainAfter
+#[utoipa::path(
+    tag = "totp",
+    request_body = TotpCodeRequest,
+    responses((status = 200, body = TotpCodeResponse)),
+)]
+#[post("/totp_recovery_codes")]
+async fn totp_recovery_codes(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    limiter: web::Data<Arc<AttemptLimiter>>,
+    form: web::Json<TotpCodeRequest>,
+) -> Result<impl Responder, APIError> {
+    let location = "totp_recovery_codes";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+    let limit_keys = [LimitKey::SecondFactor(user_id)];
+    if let Some(locked_until) = limiter.check(&limit_keys).await {
+        return Ok(enum_json(TotpCodeResponse::TryAgainAfter(
+            locked_until.timestamp(),
+        )));
+    }
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let user = load_user(user_id, &mut conn, location).await?;
+    let result = if user.totp_enabled_at.is_none() {
+        TotpCodeResponse::NotEnrolled
+    } else if check_second_factor(&user, &form.code, &mut conn, location).await? {
+        TotpCodeResponse::Regenerated(
+            conn.transaction::<_, APIError, _>(|conn| {
+                Box::pin(async move { regenerate_recovery_codes(user_id, conn).await })
+            })
+            .await
+            .map_err(|e| e.set_location(location).tap(APIError::log))?,
+        )
+    } else {
+        limiter.on_failure(&limit_keys).await;
+        TotpCodeResponse::InvalidCode
+    };
+
+    Ok(enum_json(result))
+}
+
+// [[API]]
+// desp: Disable the second factor, authorized by a TOTP or a recovery code.
+// Method: POST
+// URL: /totp_disable
+// Request Body: `TotpCodeRequest`
+// Response Body: `TotpCodeResponse`, one of Disabled, InvalidCode, NotEnrolled, TryAgainAfter
+#[utoipa::path(
+    tag = "totp",
+    request_body = TotpCodeRequest,
+    responses((status = 200, body = TotpCodeResponse)),
+)]
+#[post("/totp_disable")]
+async fn totp_disable(
+    session: Session,
+    pool: web::Data<Arc<DbPool>>,
+    limiter: web::Data<Arc<AttemptLimiter>>,
+    form: web::Json<TotpCodeRequest>,
+) -> Result<impl Responder, APIError> {
+    let location = "totp_disable";
+    form.sanity()?;
+
+    let (user_id, _) = user_privilege_check(&session, PRIVILEGE_MINIMAL)?;
+    let limit_keys = [LimitKey::SecondFactor(user_id)];
+    if let Some(locked_until) = limiter.check(&limit_keys).await {
+        return Ok(enum_json(TotpCodeResponse::TryAgainAfter(
+            locked_until.timestamp(),
+        )));
+    }
+
+    let mut conn = pool
+        .get()
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_DB_CONNECTION))?;
+
+    let user = load_user(user_id, &mut conn, location).await?;
+    let result = if user.totp_enabled_at.is_none() {
+        TotpCodeResponse::NotEnrolled
+    } else if check_second_factor(&user, &form.code, &mut conn, location).await? {
+        conn.transaction::<_, APIError, _>(|conn| {
+            Box::pin(async move {
+                diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set((
+                        users::totp_secret.eq::<Option<String>>(None),
+                        users::totp_enabled_at.eq::<Option<DateTime<Utc>>>(None),
+                        users::totp_last_step.eq(0),
+                    ))
+                    .execute(conn)
+                    .await?;
+                diesel::delete(
+                    totp_recovery_code::table.filter(totp_recovery_code::user.eq(user_id)),
+                )
+                .execute(conn)
+                .await?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| e.set_location(location).tap(APIError::log))?;
+        TotpCodeResponse::Disabled
+    } else {
+        limiter.on_failure(&limit_keys).await;
+        TotpCodeResponse::InvalidCode
+    };
+
+    Ok(enum_json(result))
+}
@@ -4,106 +4,98 @@ extern crate dotenv;
 use std::sync::Arc;
 
 use actix_cors::Cors;
-use actix_web::dev::RequestHead;
-use actix_web::http::header::HeaderValue;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
 
 use diesel_async::pooled_connection::{bb8::Pool, AsyncDieselConnectionManager};
 use diesel_async::AsyncPgConnection;
 
-use server::api::{email, monitor, oracle, puzzle, register, team};
-use server::util::{cache::Cache, cipher_util};
+use server::api;
+use server::util::config::{config, config_path, ServerConfig};
+use server::util::error_negotiation::negotiate_error;
+use server::util::mailer::mailer_from_config;
+use server::util::metrics::record_metrics;
+use server::util::notify::Notifier;
+use server::util::rate_limit::{rate_limit, RateLimitConfig, RateLimiter};
+use server::util::session_guard::session_guard;
+use server::util::{attempt_limiter::AttemptLimiter, cache::Cache, cipher_util};
 
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
 use log::warn;
 use server::DbPool;
 
-fn cors_check(head: &HeaderValue, _: &RequestHead) -> bool {
-    if let Ok(origin) = head.to_str() {
-        match origin {
-            "https://2025.yuanyang.app" => true,
-            "https://yuanyang.app" => true,
-            "http://localhost:5173" => true,
-            url => url.ends_with("yuanyang25-front.netlify.app"), // for deploy preview
-        }
-    } else {
-        false
-    }
-}
-
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let cookie_token = std::env::var("COOKIE_TOKEN").expect("COOKIE_TOKEN must be set");
+    ServerConfig::load(&config_path())
+        .and_then(ServerConfig::install)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let config = config();
 
-    let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(database_url);
+    let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(&config.database.url);
     let pool: DbPool = Pool::builder()
+        .max_size(config.database.pool_size)
         .build(manager)
         .await
         .expect("Failed to link to db");
 
-    let secret_key = cipher_util::gen_cookie_key(&cookie_token);
+    let secret_key = cipher_util::gen_cookie_key(&config.secrets.cookie_token);
 
-    let is_production = match std::env::var("MODE") {
-        Ok(mode) if mode == "dev" => {
-            warn!("Under development mode.");
-            false
-        }
-        _ => true, // Production mode as default!
-    };
+    let http = &config.http;
+    if !http.production {
+        warn!("Under development mode.");
+    }
 
     let pool = Arc::new(pool);
-    let cache = Arc::new(Cache::new(pool.clone()));
+    let cache = Arc::new(Cache::new(pool.clone(), &config.cache));
+    let limiter = Arc::new(AttemptLimiter::new());
 
-    HttpServer::new(move || {
+    let rate_limit_config = RateLimitConfig::load(&http.rate_limit_config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let rate_limiter = Arc::new(RateLimiter::new(rate_limit_config));
+    let mailer = mailer_from_config(&config.mail)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let notifier = Arc::new(Notifier::new(mailer.clone(), pool.clone()));
+
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(cache.clone()))
+            .app_data(web::Data::new(limiter.clone()))
+            .app_data(web::Data::new(rate_limiter.clone()))
+            .app_data(web::Data::new(mailer.clone()))
+            .app_data(web::Data::new(notifier.clone()))
+            .wrap(from_fn(session_guard))
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(negotiate_error))
+            .wrap(from_fn(record_metrics))
             .wrap(
                 Cors::default()
-                    .allowed_origin_fn(cors_check)
+                    .allowed_origin_fn(|origin, _| {
+                        origin
+                            .to_str()
+                            .is_ok_and(|origin| http.allows_origin(origin))
+                    })
                     .allow_any_header()
                     .allow_any_method()
                     .supports_credentials(),
             )
             .wrap(
                 SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
-                    .cookie_secure(is_production) // 在生产环境下使用 `Secure`，在开发模式下可以禁用
-                    .cookie_same_site(if is_production {
-                        actix_web::cookie::SameSite::None
-                    } else {
-                        actix_web::cookie::SameSite::Lax
-                    }) // 设置 SameSite=None 以支持跨站点请求
+                    .cookie_secure(http.production) // 在生产环境下使用 `Secure`，在开发模式下可以禁用
+                    .cookie_same_site(http.same_site()) // 生产环境默认 SameSite=None 以支持跨站点请求
                     .build(),
             )
-            .service(register::register_user)
-            .service(register::get_user)
-            .service(register::login_user)
-            .service(register::logout)
-            .service(team::create_team)
-            .service(team::team_veri)
-            .service(team::join_team)
-            .service(team::exit_team)
-            .service(team::info)
-            .service(puzzle::decipher_key)
-            .service(puzzle::submit_answer)
-            .service(puzzle::unlock)
-            .service(puzzle::puzzle_status)
-            .service(puzzle::rank)
-            .service(monitor::cache_size)
-            .service(oracle::create_oracle)
-            .service(oracle::get_oracle)
-            .service(oracle::check_oracle)
-            .service(oracle::staff_list_oracle)
-            .service(oracle::staff_reply_oracle)
-            .service(oracle::staff_work_from)
-            .service(email::get_email)
-            .service(email::post_email)
-    })
-    .bind("0.0.0.0:9000")?
+            .configure(api::configure)
+    });
+
+    match http.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    }
+    .bind(http.bind)?
     .run()
     .await
 }
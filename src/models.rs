@@ -1,6 +1,7 @@
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::Serialize;
+use utoipa::ToSchema;
 
 pub type TeamId = i32;
 pub type PuzzleId = i32;
@@ -17,6 +18,12 @@ pub struct User {
     pub password: String,
     pub salt: String,
     pub privilege: i32,
+    pub password_scheme: i32,
+    pub session_version: i32,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    pub totp_last_step: i64,
+    pub locale: Option<String>,
 }
 
 #[derive(Queryable, Selectable, Clone)]
@@ -29,6 +36,7 @@ pub struct Team {
     pub max_size: i32,
     pub size: i32,
     pub salt: String,
+    pub captain: Option<UserId>,
 }
 
 #[derive(Queryable, Selectable, Clone)]
@@ -37,6 +45,7 @@ pub struct Unlock {
     pub level: i32,
     pub team: TeamId,
     pub decipher: DecipherId,
+    pub unlocked_at: DateTime<Utc>,
 }
 
 #[derive(Queryable, Selectable, Clone)]
@@ -47,6 +56,8 @@ pub struct PuzzleBase {
     pub title: String,
     pub decipher: i32,
     pub depth: i32,
+    pub available_from: Option<DateTime<Utc>>,
+    pub available_until: Option<DateTime<Utc>>,
 }
 
 #[derive(Queryable, Selectable, Clone)]
@@ -55,6 +66,8 @@ pub struct WaPenalty {
     pub time_penalty_until: DateTime<Utc>,
     pub token_penalty_level: i32,
     pub time_penalty_level: i32,
+    pub wrong_attempts: i32,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Queryable, Selectable, Clone)]
@@ -64,6 +77,8 @@ pub struct Decipher {
     pub base_price: i32,
     pub depth: i32,
     pub root: String,
+    pub available_from: Option<DateTime<Utc>>,
+    pub available_until: Option<DateTime<Utc>>,
 }
 
 #[derive(Insertable)]
@@ -77,7 +92,7 @@ pub struct NewOracle<'a> {
     pub active: bool,
 }
 
-#[derive(Queryable, Serialize)]
+#[derive(Queryable, Serialize, ToSchema)]
 #[diesel(table_name = crate::schema::oracle)]
 pub struct OracleRecord {
     pub id: i32,
@@ -90,14 +105,14 @@ pub struct OracleRecord {
     pub response: String,
 }
 
-#[derive(Queryable, Serialize)]
+#[derive(Queryable, Serialize, ToSchema)]
 #[diesel(table_name = crate::schema::oracle)]
 pub struct OracleSummary {
     pub id: i32,
     pub active: bool,
 }
 
-#[derive(Queryable, Serialize)]
+#[derive(Queryable, Serialize, ToSchema)]
 #[diesel(table_name = crate::schema::oracle)]
 pub struct OracleSummaryStaff {
     pub id: i32,
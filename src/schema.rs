@@ -1,5 +1,16 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    announcement (id) {
+        id -> Int4,
+        author -> Int4,
+        #[max_length = 255]
+        title -> Varchar,
+        content -> Text,
+        time -> Timestamptz,
+    }
+}
+
 diesel::table! {
     answer (id) {
         id -> Int4,
@@ -10,6 +21,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    consumed_token (token_hash) {
+        #[max_length = 64]
+        token_hash -> Bpchar,
+        consumed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     decipher (id) {
         id -> Int4,
@@ -18,6 +37,8 @@ diesel::table! {
         depth -> Int4,
         #[max_length = 64]
         root -> Bpchar,
+        available_from -> Nullable<Timestamptz>,
+        available_until -> Nullable<Timestamptz>,
     }
 }
 
@@ -27,6 +48,24 @@ diesel::table! {
         user -> Int4,
         #[max_length = 255]
         email_record -> Varchar,
+        verified_at -> Nullable<Timestamptz>,
+        #[max_length = 64]
+        code_hash -> Nullable<Bpchar>,
+        code_attempts -> Int4,
+        code_expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    email_signup_code (id) {
+        id -> Int4,
+        #[max_length = 255]
+        email -> Varchar,
+        #[max_length = 64]
+        code_hash -> Bpchar,
+        attempts -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
     }
 }
 
@@ -41,6 +80,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    game_phase (id) {
+        id -> Int4,
+        phase -> Int4,
+        changed_by -> Int4,
+        changed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     oracle (id) {
         id -> Int4,
@@ -51,6 +99,7 @@ diesel::table! {
         query -> Text,
         response -> Text,
         active -> Bool,
+        created_at -> Timestamptz,
     }
 }
 
@@ -75,6 +124,29 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    password_reset (id) {
+        id -> Int4,
+        user -> Int4,
+        #[max_length = 64]
+        token_hash -> Bpchar,
+        used -> Bool,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    penalty_schedule (id) {
+        id -> Int4,
+        time_penalty -> Array<Int8>,
+        token_penalty -> Array<Int8>,
+        free_attempts -> Int4,
+        decay_minutes -> Nullable<Int4>,
+        reset_time_on_mid_answer -> Bool,
+    }
+}
+
 diesel::table! {
     puzzle (id) {
         id -> Int4,
@@ -84,6 +156,38 @@ diesel::table! {
         title -> Varchar,
         decipher -> Int4,
         depth -> Int4,
+        round -> Nullable<Int4>,
+        unlock_rule -> Int4,
+        unlock_count -> Int4,
+        available_from -> Nullable<Timestamptz>,
+        available_until -> Nullable<Timestamptz>,
+        penalty_schedule -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    puzzle_prerequisite (id) {
+        id -> Int4,
+        puzzle -> Int4,
+        requires -> Int4,
+    }
+}
+
+diesel::table! {
+    puzzle_round (id) {
+        id -> Int4,
+        #[max_length = 64]
+        title -> Varchar,
+        opens_at -> Timestamptz,
+        penalty_schedule -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    puzzle_summary (puzzle) {
+        puzzle -> Int4,
+        teams_passed -> Int8,
+        teams_unlocked -> Int8,
     }
 }
 
@@ -109,6 +213,32 @@ diesel::table! {
         size -> Int4,
         #[max_length = 64]
         salt -> Varchar,
+        captain -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    team_invite (id) {
+        id -> Int4,
+        team -> Int4,
+        #[max_length = 64]
+        token_hash -> Bpchar,
+        created_by -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        max_uses -> Int4,
+        uses -> Int4,
+        revoked -> Bool,
+    }
+}
+
+diesel::table! {
+    totp_recovery_code (id) {
+        id -> Int4,
+        user -> Int4,
+        #[max_length = 64]
+        code_hash -> Bpchar,
+        used_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -132,6 +262,7 @@ diesel::table! {
         team -> Int4,
         decipher -> Int4,
         level -> Int4,
+        unlocked_at -> Timestamptz,
     }
 }
 
@@ -143,11 +274,19 @@ diesel::table! {
         team -> Nullable<Int4>,
         #[max_length = 255]
         username -> Varchar,
-        #[max_length = 64]
+        #[max_length = 255]
         password -> Varchar,
         #[max_length = 64]
         salt -> Varchar,
         privilege -> Int4,
+        password_scheme -> Int4,
+        session_version -> Int4,
+        #[max_length = 64]
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamptz>,
+        totp_last_step -> Int8,
+        #[max_length = 16]
+        locale -> Nullable<Varchar>,
     }
 }
 
@@ -159,6 +298,8 @@ diesel::table! {
         token_penalty_level -> Int4,
         time_penalty_level -> Int4,
         time_penalty_until -> Timestamptz,
+        wrong_attempts -> Int4,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -170,8 +311,15 @@ diesel::joinable!(oracle -> team (team));
 diesel::joinable!(other_answer -> puzzle (puzzle));
 diesel::joinable!(other_answer_submission -> other_answer (other_answer));
 diesel::joinable!(other_answer_submission -> team (team));
+diesel::joinable!(password_reset -> users (user));
+diesel::joinable!(puzzle -> penalty_schedule (penalty_schedule));
+diesel::joinable!(puzzle -> puzzle_round (round));
+diesel::joinable!(puzzle_round -> penalty_schedule (penalty_schedule));
+diesel::joinable!(puzzle_summary -> puzzle (puzzle));
 diesel::joinable!(submission -> puzzle (puzzle));
 diesel::joinable!(submission -> team (team));
+diesel::joinable!(team_invite -> team (team));
+diesel::joinable!(totp_recovery_code -> users (user));
 diesel::joinable!(transaction -> team (team));
 diesel::joinable!(unlock -> decipher (decipher));
 diesel::joinable!(unlock -> team (team));
@@ -179,16 +327,27 @@ diesel::joinable!(wrong_answer_cnt -> puzzle (puzzle));
 diesel::joinable!(wrong_answer_cnt -> team (team));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    announcement,
     answer,
+    consumed_token,
     decipher,
     email,
+    email_signup_code,
     final_meta_submission,
+    game_phase,
     oracle,
     other_answer,
     other_answer_submission,
+    password_reset,
+    penalty_schedule,
     puzzle,
+    puzzle_prerequisite,
+    puzzle_round,
+    puzzle_summary,
     submission,
     team,
+    team_invite,
+    totp_recovery_code,
     transaction,
     unlock,
     users,
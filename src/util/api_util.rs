@@ -1,19 +1,22 @@
+use std::fmt;
+use std::net::IpAddr;
 use std::ops::DerefMut;
 
 use actix_session::Session;
-use actix_web::{
-    error,
-    http::{header::ContentType, StatusCode},
-    HttpResponse,
-};
-use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use actix_web::http::header::{RETRY_AFTER, X_FORWARDED_FOR};
+use actix_web::{error, http::StatusCode, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
 use diesel::result::Error;
 
-use derive_more::derive::Display;
 use diesel::prelude::*;
 
+use crate::util::config::config;
+use crate::util::i18n::{t, t_args, Locale};
+use crate::util::metrics::metrics;
 use crate::{models::*, util::economy::time_allowance, DbPool, Ext};
 use log::error;
+use serde::Serialize;
+use utoipa::ToSchema;
 
 use diesel_async::AsyncPgConnection;
 use diesel_async::RunQueryDsl;
@@ -29,33 +32,27 @@ pub trait APIRequest: Sized {
     }
 }
 
-#[derive(Debug, Display, PartialEq, Eq)]
+// Messages are in `locales/`, keyed by `error.<code>`.
+#[derive(Debug, PartialEq, Eq)]
 pub enum APIError {
-    #[display("请求格式或字段长度不正确")]
     InvalidFormData,
-
-    #[display("请求参数不正确")]
     InvalidQuery,
-
-    #[display("Cookie 不合法")]
     InvalidSession,
-
-    #[display("未登录")]
     NotLogin,
-
-    #[display("不在队伍中")]
     NotInTeam,
-
-    #[display("余额不足")]
     InsufficientTokens,
-
-    #[display("权限不足")]
     Unauthorized,
-
-    #[display("交易未执行，当前余额 {balance}")]
-    TransactionCancel { balance: i64 },
-
-    #[display("服务器内部错误： {location}, ref[{refnum}]: {msg}")]
+    NotFound,
+    NotAvailable,
+    TransactionCancel {
+        balance: i64,
+    },
+    // Rejected by `rate_limit`, `scope` is the bucket that ran out.
+    TooManyRequests {
+        route: String,
+        scope: &'static str,
+        retry_after: u64,
+    },
     ServerError {
         location: &'static str,
         msg: &'static str,
@@ -97,16 +94,118 @@ impl From<Error> for APIError {
     }
 }
 
+/// The JSON body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable and machine-readable, e.g. "not_login".
+    pub code: &'static str,
+    /// Human-readable, may change at any time.
+    pub message: String,
+    /// Quote it when reporting a server error.
+    pub refnum: Option<uuid::Uuid>,
+    pub details: Option<serde_json::Value>,
+}
+
+impl APIError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            APIError::InvalidFormData => "invalid_form_data",
+            APIError::InvalidQuery => "invalid_query",
+            APIError::InvalidSession => "invalid_session",
+            APIError::NotLogin => "not_login",
+            APIError::NotInTeam => "not_in_team",
+            APIError::InsufficientTokens => "insufficient_tokens",
+            APIError::Unauthorized => "unauthorized",
+            APIError::NotFound => "not_found",
+            APIError::NotAvailable => "not_available",
+            APIError::TransactionCancel { balance: _ } => "transaction_cancel",
+            APIError::TooManyRequests { .. } => "too_many_requests",
+            APIError::ServerError {
+                location: _,
+                msg: _,
+                refnum: _,
+            } => "server_error",
+        }
+    }
+
+    pub fn message(&self, locale: Locale) -> String {
+        let key = format!("error.{}", self.code());
+        match self {
+            APIError::TransactionCancel { balance } => {
+                t_args(locale, &key, &[("balance", balance.to_string())])
+            }
+            APIError::TooManyRequests { retry_after, .. } => {
+                t_args(locale, &key, &[("retry_after", retry_after.to_string())])
+            }
+            APIError::ServerError {
+                location,
+                msg,
+                refnum,
+            } => t_args(
+                locale,
+                &key,
+                &[
+                    ("location", location.to_string()),
+                    ("msg", msg.to_string()),
+                    ("refnum", refnum.to_string()),
+                ],
+            ),
+            _ => t(locale, &key),
+        }
+    }
+
+    pub fn body(&self, locale: Locale) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.message(locale),
+            refnum: match self {
+                APIError::ServerError { refnum, .. } => Some(*refnum),
+                _ => None,
+            },
+            details: match self {
+                APIError::TransactionCancel { balance } => {
+                    Some(serde_json::json!({ "balance": balance }))
+                }
+                APIError::TooManyRequests {
+                    route,
+                    scope,
+                    retry_after,
+                } => Some(serde_json::json!({
+                    "route": route,
+                    "scope": scope,
+                    "retry_after": retry_after,
+                })),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for APIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message(Locale::default()))
+    }
+}
+
 impl error::ResponseError for APIError {
+    // Localized, or plain text for clients not accepting JSON, by `negotiate_error`.
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::html())
-            .body(self.to_string())
+        metrics().count_error(self.code());
+        let mut response = HttpResponse::build(self.status_code());
+        if let APIError::TooManyRequests { retry_after, .. } = self {
+            response.insert_header((RETRY_AFTER, *retry_after));
+        }
+        response.json(self.body(Locale::default()))
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
             APIError::InvalidFormData => StatusCode::NOT_ACCEPTABLE,
+            APIError::InvalidSession | APIError::NotLogin => StatusCode::UNAUTHORIZED,
+            APIError::Unauthorized | APIError::NotAvailable => StatusCode::FORBIDDEN,
+            APIError::NotFound => StatusCode::NOT_FOUND,
+            APIError::TransactionCancel { balance: _ } => StatusCode::CONFLICT,
+            APIError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             APIError::ServerError {
                 location: _,
                 msg: _,
@@ -232,49 +331,6 @@ where
     }
 }
 
-static TIME_PENALTY: [i64; 11] = [21, 29, 41, 57, 80, 112, 156, 219, 306, 429, 600]; // in seconds
-static TOKEN_PENALTY: [i64; 11] = [129, 155, 186, 223, 268, 322, 386, 463, 556, 667, 800];
-
-impl Default for WaPenalty {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl WaPenalty {
-    pub fn new() -> Self {
-        Self {
-            time_penalty_until: Utc.timestamp_opt(1, 0).unwrap(),
-            token_penalty_level: 0,
-            time_penalty_level: 0,
-        }
-    }
-
-    pub fn on_wrong_answer(&mut self) -> i64 {
-        let time_penalty = TIME_PENALTY
-            .get(self.time_penalty_level as usize)
-            .or(TIME_PENALTY.last())
-            .cloned()
-            .unwrap_or(600);
-        let token_penalty = TOKEN_PENALTY
-            .get(self.token_penalty_level as usize)
-            .or(TOKEN_PENALTY.last())
-            .cloned()
-            .unwrap_or(500);
-        self.token_penalty_level += 1;
-        self.time_penalty_level += 1;
-        self.time_penalty_until = Utc::now() + TimeDelta::seconds(time_penalty);
-        token_penalty
-    }
-
-    pub fn on_new_mid_answer(self) -> Self {
-        Self {
-            token_penalty_level: 0,
-            ..self
-        }
-    }
-}
-
 pub fn check_is_after(to_check: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
     (now <= to_check).then_some(to_check)
 }
@@ -291,22 +347,38 @@ where
 
     match wrong_answer_cnt
         .filter(team.eq(team_id).and(puzzle.eq(puzzle_id)))
-        .select((time_penalty_until, token_penalty_level, time_penalty_level))
-        .first::<(DateTime<Utc>, i32, i32)>(conn)
+        .select(WaPenalty::as_select())
+        .first::<WaPenalty>(conn)
         .await
     {
-        Ok((time_penalty_until_, token_penalty_level_, time_penalty_level_)) => {
-            Ok(Some(WaPenalty {
-                time_penalty_until: time_penalty_until_,
-                token_penalty_level: token_penalty_level_,
-                time_penalty_level: time_penalty_level_,
-            }))
-        }
+        Ok(penalty) => Ok(Some(penalty)),
         Err(Error::NotFound) => Ok(None),
         Err(e) => Err(new_unlocated_server_error(e, ERROR_DB_UNKNOWN)),
     }
 }
 
+/// Whether the team has submitted the final answer of the puzzle.
+pub async fn check_solved<C>(
+    puzzle_id: PuzzleId,
+    team_id: TeamId,
+    conn: &mut C,
+) -> Result<bool, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::submission::dsl::*;
+
+    diesel::select(diesel::dsl::exists(
+        submission
+            .filter(team.eq(team_id))
+            .filter(puzzle.eq(puzzle_id))
+            .filter(depth.eq(0)),
+    ))
+    .get_result::<bool>(conn)
+    .await
+    .map_err(|e| new_unlocated_server_error(e, ERROR_DB_UNKNOWN))
+}
+
 pub async fn count_passed<C>(team_id: TeamId, conn: &mut C) -> Result<usize, APIError>
 where
     C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
@@ -338,6 +410,8 @@ where
             token_penalty_level.eq(data.token_penalty_level),
             time_penalty_level.eq(data.time_penalty_level),
             time_penalty_until.eq(data.time_penalty_until),
+            wrong_attempts.eq(data.wrong_attempts),
+            updated_at.eq(data.updated_at),
         ))
         .on_conflict((team, puzzle))
         .do_update()
@@ -345,6 +419,8 @@ where
             token_penalty_level.eq(data.token_penalty_level),
             time_penalty_level.eq(data.time_penalty_level),
             time_penalty_until.eq(data.time_penalty_until),
+            wrong_attempts.eq(data.wrong_attempts),
+            updated_at.eq(data.updated_at),
         ))
         .execute(conn)
         .await
@@ -353,20 +429,32 @@ where
     Ok(())
 }
 
+/// Changing the address resets its verification, unless `verified` is set.
 pub async fn insert_or_update_email<C>(
     user_id: i32,
     user_email: String,
+    verified: bool,
     conn: &mut C,
 ) -> Result<(), APIError>
 where
     C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
 {
-    use crate::schema::email::dsl::*;
-    diesel::insert_into(email)
-        .values((user.eq(user_id), email_record.eq(user_email.as_str())))
-        .on_conflict(user)
-        .do_update()
-        .set(email_record.eq(user_email.as_str()))
+    let query = r#"
+        INSERT INTO "email" ("user", "email_record", "verified_at")
+        VALUES ($1, $2, $3)
+        ON CONFLICT ("user") DO UPDATE SET
+            "verified_at" = CASE
+                WHEN "email"."email_record" = EXCLUDED."email_record"
+                THEN COALESCE(EXCLUDED."verified_at", "email"."verified_at")
+                ELSE EXCLUDED."verified_at"
+            END,
+            "email_record" = EXCLUDED."email_record";
+    "#;
+
+    diesel::sql_query(query)
+        .bind::<Integer, _>(user_id)
+        .bind::<Text, _>(user_email)
+        .bind::<Nullable<Timestamptz>, _>(verified.then(Utc::now))
         .execute(conn)
         .await
         .map_err(|e| new_unlocated_server_error(e, ERROR_DB_UNKNOWN))?;
@@ -484,11 +572,13 @@ where
     match result {
         Ok(i) => Ok(Some(i)),
         Err(diesel::result::Error::NotFound) => Ok(None), // 如果没有找到记录
-        Err(e) => Err(e.into()),                          // 其他错误
+        Err(e) => Err(e),                                 // 其他错误
     }
 }
 
-use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::sql_types::{BigInt, Integer, Nullable, Text, Timestamptz};
+
+define_sql_function!(fn lower(x: Text) -> Text);
 
 use diesel::QueryableByName;
 
@@ -554,6 +644,31 @@ where
     }
 }
 
+/// The peer, or the rightmost address of `X-Forwarded-For` that is not a
+/// trusted proxy if the peer is one. What the client itself sent is ignored.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let Some(peer) = req.peer_addr().map(|addr| addr.ip()) else {
+        return "unknown".to_string();
+    };
+    let trusted = &config().http.trusted_proxies;
+    if !trusted.contains(&peer) {
+        return peer.to_string();
+    }
+
+    req.headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .map_while(|addr| addr.parse::<IpAddr>().ok())
+        .find(|addr| !trusted.contains(addr))
+        .unwrap_or(peer)
+        .to_string()
+}
+
 pub fn handle_session<T>(session: &mut Session) -> impl FnMut((T, bool)) -> T + '_ {
     |(value, kill_session)| {
         if kill_session {
@@ -582,10 +697,13 @@ pub fn allow_err<T>(old: Result<T, APIError>, allow: APIError) -> Result<Option<
 pub static SESSION_USER_ID: &str = "user_id";
 pub static SESSION_PRIVILEGE: &str = "user_privilege";
 pub static SESSION_TEAM_ID: &str = "team_id";
+pub static SESSION_VERSION: &str = "session_version";
+pub static SESSION_LOCALE: &str = "locale";
 
 pub static ERROR_DB_CONNECTION: &str = "db_connction_failed";
 pub static ERROR_SESSION_INSERT: &str = "session_setting_failed";
 pub static ERROR_DB_UNKNOWN: &str = "database_unknown";
+pub static ERROR_BLOCKING: &str = "blocking_task_failed";
 
 pub static LOCATION_UNKNOWN: &str = "[unknown]";
 
@@ -0,0 +1,116 @@
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::header::ContentType;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+pub const VERSION_PREFIXES: [&str; 2] = ["/v1", "/v2"];
+
+/// Key of the variant name in the internally tagged enums of `/v2`.
+pub const TAG: &str = "type";
+
+/// Marks a response whose body is an externally tagged enum.
+#[derive(Debug, Clone, Copy)]
+struct EnumBody;
+
+/// A JSON response of an enum, which `/v2` rewrites to be internally tagged.
+pub fn enum_json<T: Serialize>(value: T) -> HttpResponse {
+    let mut response = HttpResponse::Ok().json(value);
+    response.extensions_mut().insert(EnumBody);
+    response
+}
+
+/// The route pattern without its version, so that `/login`, `/v1/login` and
+/// `/v2/login` share e.g. their rate limit.
+///
+/// ``` rust
+/// use server::util::api_version::unversioned;
+///
+/// assert_eq!(unversioned("/v2/submit_answer"), "/submit_answer");
+/// assert_eq!(unversioned("/submit_answer"), "/submit_answer");
+/// assert_eq!(unversioned("/v2x/submit_answer"), "/v2x/submit_answer");
+/// ```
+pub fn unversioned(pattern: &str) -> &str {
+    VERSION_PREFIXES
+        .iter()
+        .find_map(|prefix| {
+            pattern
+                .strip_prefix(prefix)
+                .filter(|rest| rest.starts_with('/'))
+        })
+        .unwrap_or(pattern)
+}
+
+/// Rewrites an externally tagged enum to be internally tagged. The fields of
+/// a struct variant stay beside the tag, any other content goes to `value`.
+///
+/// ``` rust
+/// use serde_json::json;
+/// use server::util::api_version::internally_tagged;
+///
+/// assert_eq!(internally_tagged(json!("Sent")), json!({"type": "Sent"}));
+/// assert_eq!(
+///     internally_tagged(json!({"Sucess": {"oracle_id": 1, "cost": 5}})),
+///     json!({"type": "Sucess", "oracle_id": 1, "cost": 5})
+/// );
+/// assert_eq!(
+///     internally_tagged(json!({"TryAgainAfter": 1700000000})),
+///     json!({"type": "TryAgainAfter", "value": 1700000000})
+/// );
+/// // The content keeps its own `type`.
+/// assert_eq!(
+///     internally_tagged(json!({"Failed": {"type": "Expired", "desp": "..."}})),
+///     json!({"type": "Failed", "value": {"type": "Expired", "desp": "..."}})
+/// );
+/// ```
+pub fn internally_tagged(value: Value) -> Value {
+    match value {
+        Value::String(variant) => {
+            Value::Object(Map::from_iter([(TAG.to_string(), variant.into())]))
+        }
+        Value::Object(map) if map.len() == 1 => {
+            let (variant, content) = map.into_iter().next().unwrap();
+            let mut tagged = match content {
+                Value::Object(fields) if !fields.contains_key(TAG) => fields,
+                content => Map::from_iter([("value".to_string(), content)]),
+            };
+            tagged.insert(TAG.to_string(), variant.into());
+            Value::Object(tagged)
+        }
+        value => value,
+    }
+}
+
+/// Middleware of `/v2`, making the enums of `enum_json` internally tagged.
+pub async fn tag_enums<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let res = next.call(req).await?;
+
+    if res.response().extensions().get::<EnumBody>().is_none() {
+        return Ok(res.map_into_boxed_body());
+    }
+
+    let (req, response) = res.into_parts();
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| ErrorInternalServerError(e.into() as Box<dyn std::error::Error>))?;
+
+    let response = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => HttpResponse::build(status).json(internally_tagged(value)),
+        // Not expected, `enum_json` always writes JSON.
+        Err(_) => HttpResponse::build(status)
+            .insert_header(ContentType::json())
+            .body(bytes),
+    };
+
+    Ok(ServiceResponse::new(req, response))
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::warn;
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::{TeamId, UserId};
+
+/// What a failed attempt is counted against. Guesses on another user or team
+/// are keyed by the IP as well, so that nobody can lock them out.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", content = "id")]
+pub enum LimitKey {
+    /// Attempts of the logged in user, e.g. vericodes to join a team.
+    User(UserId),
+    /// Passwords of a user, from an IP.
+    Login {
+        user: UserId,
+        ip: String,
+    },
+    /// The second factor of a user, from anywhere, as the codes are short.
+    /// Only tried once the password is right.
+    SecondFactor(UserId),
+    /// Vericodes of a team, from an IP.
+    Team {
+        team: TeamId,
+        ip: String,
+    },
+    Ip(String),
+}
+
+impl LimitKey {
+    // Max failures within `WINDOW_SECONDS` before getting locked.
+    fn budget(&self) -> usize {
+        match self {
+            LimitKey::User(_) => 8,
+            LimitKey::Login { .. } => 8,
+            LimitKey::SecondFactor(_) => 8,
+            LimitKey::Team { .. } => 8,
+            LimitKey::Ip(_) => 30,
+        }
+    }
+}
+
+const WINDOW_SECONDS: i64 = 600;
+const BASE_LOCK_SECONDS: i64 = 30;
+const MAX_LOCK_SECONDS: i64 = 3600;
+// Records (and with them the backoff exponent) are forgotten after a quiet day.
+const FORGET_AFTER: Duration = Duration::from_secs(86400);
+
+#[derive(Clone, Debug, Default)]
+pub struct AttemptRecord {
+    failures: Vec<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+    lock_count: u32,
+}
+
+impl AttemptRecord {
+    fn locked(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.locked_until.filter(|until| *until > now)
+    }
+
+    fn on_failure(mut self, key: &LimitKey, now: DateTime<Utc>) -> Self {
+        self.failures
+            .retain(|time| now - *time < TimeDelta::seconds(WINDOW_SECONDS));
+        self.failures.push(now);
+
+        if self.failures.len() >= key.budget() {
+            // 30s, 60s, 120s, ... up to an hour
+            let lock_seconds = BASE_LOCK_SECONDS
+                .saturating_mul(1 << self.lock_count.min(16))
+                .min(MAX_LOCK_SECONDS);
+            self.locked_until = Some(now + TimeDelta::seconds(lock_seconds));
+            self.lock_count += 1;
+            self.failures.clear();
+            warn!("{key:?} locked for {lock_seconds} seconds");
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LockStatus {
+    key: LimitKey,
+    recent_failures: usize,
+    locked_until: Option<i64>, // unix timestamp in seconds
+    lock_count: u32,
+}
+
+/// Sliding window limiter for guessable credentials (passwords, vericodes).
+pub struct AttemptLimiter {
+    records: Cache<LimitKey, AttemptRecord>,
+}
+
+impl Default for AttemptLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttemptLimiter {
+    pub fn new() -> Self {
+        Self {
+            records: Cache::builder()
+                .max_capacity(65536)
+                .time_to_idle(FORGET_AFTER)
+                .build(),
+        }
+    }
+
+    /// Returns the time until which any of the keys is locked.
+    pub async fn check(&self, keys: &[LimitKey]) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let mut result = None;
+        for key in keys {
+            if let Some(until) = self
+                .records
+                .get(key)
+                .await
+                .and_then(|record| record.locked(now))
+            {
+                result = result.max(Some(until));
+            }
+        }
+        result
+    }
+
+    pub async fn on_failure(&self, keys: &[LimitKey]) {
+        let now = Utc::now();
+        for key in keys {
+            self.records
+                .entry(key.clone())
+                .and_upsert_with(|old| {
+                    let record = old.map(|entry| entry.into_value()).unwrap_or_default();
+                    std::future::ready(record.on_failure(key, now))
+                })
+                .await;
+        }
+    }
+
+    /// Only the failure window is reset, the backoff exponent is kept.
+    pub async fn on_success(&self, key: &LimitKey) {
+        if let Some(mut record) = self.records.get(key).await {
+            record.failures.clear();
+            self.records.insert(key.clone(), record).await;
+        }
+    }
+
+    pub async fn clear(&self, key: &LimitKey) {
+        self.records.invalidate(key).await
+    }
+
+    pub fn list(&self) -> Vec<LockStatus> {
+        let now = Utc::now();
+        self.records
+            .iter()
+            .filter(|(_, record)| record.locked(now).is_some() || !record.failures.is_empty())
+            .map(|(key, record)| LockStatus {
+                key: (*key).clone(),
+                recent_failures: record.failures.len(),
+                locked_until: record.locked(now).map(|until| until.timestamp()),
+                lock_count: record.lock_count,
+            })
+            .collect()
+    }
+}
@@ -2,12 +2,15 @@ use log::{debug, info};
 use moka::future::Cache;
 use moka::notification::RemovalCause;
 use moka::Expiry;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::task::JoinHandle;
 
 use std::fmt::Debug;
 
+use super::metrics::{metrics, CacheCounters};
+
 /// An enum to represent the expiration of a value.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Expiration {
@@ -70,6 +73,7 @@ where
 {
     cache: Cache<K, (Expiration, V)>,
     capacity: usize,
+    counters: Arc<CacheCounters>,
     value_loader: Arc<F>,
     value_writer: Arc<G>,
 }
@@ -86,14 +90,23 @@ where
     F: Fn(K) -> AutoCacheReadHandle<V, E> + Send + Sync + 'static, // read from db
     G: Fn(K, V) -> AutoCacheWriteHandle<E> + Send + Sync + 'static, // write into db
 {
-    pub fn new(capacity: usize, value_loader: F, value_writer: G) -> Self {
+    /// `name` labels the hits, misses and evictions in `/metrics`.
+    pub fn new(name: &'static str, capacity: usize, value_loader: F, value_writer: G) -> Self {
+        let counters = metrics().cache(name);
+        let evictions = counters.clone();
         Self {
             cache: Cache::builder()
                 .max_capacity(capacity as u64) // 设置 LRU 驱逐策略
                 .expire_after(MyExpiry)
-                .eviction_listener(eviction_listener)
+                .eviction_listener(move |key, value, cause| {
+                    if cause.was_evicted() {
+                        evictions.evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    eviction_listener(key, value, cause)
+                })
                 .build(),
             capacity,
+            counters,
             value_loader: Arc::new(value_loader),
             value_writer: Arc::new(value_writer),
         }
@@ -106,8 +119,10 @@ where
     pub async fn get(&self, key: K) -> Result<V, E> {
         if let Some(value) = self.cache.get(&key).await {
             debug!("Got cached key {key:?} -> {}", std::any::type_name::<V>());
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
             return Ok(value.1);
         }
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
 
         let value_loader = self.value_loader.clone();
         let key_clone = key.clone();
@@ -130,11 +145,11 @@ where
         Ok(value)
     }
 
-    // write through
+    // write through, replacing what was cached by an earlier `get`
     pub async fn set(&self, key: K, value: V, expiry: Expiration) -> Result<(), E> {
         if expiry != Expiration::AtOnce {
             self.cache
-                .get_with(key.clone(), async { (expiry, value.clone()) })
+                .insert(key.clone(), (expiry, value.clone()))
                 .await;
             debug!(
                 "Caching setted key {key:?} -> {}",
@@ -9,16 +9,23 @@ use std::sync::Arc;
 use crate::api::puzzle::Puzzle;
 use crate::models::*;
 use crate::util::api_util::ERROR_DB_CONNECTION;
+use crate::util::config::CacheConfig;
 
 use crate::util::auto_fetch::Expiration;
 
 use super::{
     api_util::{log_server_error, APIError},
     auto_fetch::MyExpiry,
-    stat::{fetch_statistic, PuzzleStatistic},
+    game_phase::{fetch_phase, PhaseState},
+    penalty::fetch_penalty_schedule,
+    puzzle_graph::{fetch_graph, PuzzleGraph},
+    stat::{
+        fetch_activity, fetch_frozen_statistic, fetch_solve_statistic, fetch_statistic, Activity,
+        ActivityFilter, PuzzleStatistic, SolveStatistic,
+    },
 };
 
-use crate::DbPool;
+use crate::{DbPool, Ext};
 
 use super::auto_fetch::{AutoCache, AutoCacheReadHandle, AutoCacheWriteHandle};
 
@@ -38,16 +45,25 @@ pub struct Cache {
     pub puzzle_cache: APICache<PuzzleId, Arc<Puzzle>>,
     pub time_punish_cache: APICache<(TeamId, PuzzleId), DateTime<Utc>>,
     pub decipher_cache: APICache<DecipherId, Arc<Decipher>>,
-    pub stat: MokaCache<(), (Expiration, Arc<PuzzleStatistic>)>,
+    pub session_version_cache: APICache<UserId, Option<i32>>,
+    pub stat: MokaCache<Option<DateTime<Utc>>, (Expiration, Arc<PuzzleStatistic>)>, // by frozen_at
+    pub solve_stat: MokaCache<(), (Expiration, Arc<SolveStatistic>)>,
+    pub activity: MokaCache<ActivityFilter, (Expiration, Arc<Activity>)>,
+    pub puzzle_graph: MokaCache<(), (Expiration, Arc<PuzzleGraph>)>,
+    pub game_phase: MokaCache<(), (Expiration, PhaseState)>,
+    // Hashes of consumed register tokens, in front of the `consumed_token` table.
+    pub consumed_token: MokaCache<String, ()>,
     pool: Arc<DbPool>,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
 pub struct CacheStatusResponse {
     unlock: (usize, usize),
     puzzle: (usize, usize),
     time_punish: (usize, usize),
     decipher: (usize, usize),
+    session_version: (usize, usize),
+    consumed_token: u64,
 }
 
 fn fetchdb_unlock_level(
@@ -127,12 +143,14 @@ fn fetchdb_puzzle(
                 puzzle_dsl::title,
                 puzzle_dsl::decipher,
                 puzzle_dsl::depth,
+                puzzle_dsl::available_from,
+                puzzle_dsl::available_until,
             ))
             .first::<PuzzleBase>(&mut conn)
             .await
         {
             Ok(p) => Ok(p),
-            Err(Error::NotFound) => Err(APIError::InvalidQuery),
+            Err(Error::NotFound) => Err(APIError::NotFound),
             Err(err) => Err(log_server_error(err, "cache", ERROR_DB_CONNECTION)),
         }?;
 
@@ -162,6 +180,10 @@ fn fetchdb_puzzle(
             Err(err) => Err(log_server_error(err, "cache", ERROR_DB_CONNECTION)),
         }?;
 
+        let penalty = fetch_penalty_schedule(puzzle_id, &mut conn)
+            .await
+            .map_err(|e| e.set_location("cache").tap(APIError::log))?;
+
         Ok((
             Arc::new(Puzzle::new(
                 puzzle_item,
@@ -170,6 +192,7 @@ fn fetchdb_puzzle(
                     .into_iter()
                     .map(|(sha, content, refnum)| (sha, (refnum, content)))
                     .collect(),
+                penalty,
             )),
             Expiration::Long,
         ))
@@ -189,12 +212,19 @@ fn fetchdb_decipher(
             .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
         let item = match decipher
             .filter(id.eq(decipher_id))
-            .select((pricing_type, base_price, depth, root))
+            .select((
+                pricing_type,
+                base_price,
+                depth,
+                root,
+                available_from,
+                available_until,
+            ))
             .first::<Decipher>(&mut conn)
             .await
         {
             Ok(p) => Ok(p),
-            Err(Error::NotFound) => Err(APIError::InvalidQuery),
+            Err(Error::NotFound) => Err(APIError::NotFound),
             Err(err) => Err(log_server_error(err, "cache", ERROR_DB_CONNECTION)),
         }?;
 
@@ -227,9 +257,33 @@ fn fetchdb_time_punish(
     })
 }
 
+fn fetchdb_session_version(
+    pool: Arc<DbPool>,
+    user_id: UserId,
+) -> AutoCacheReadHandle<Option<i32>, APIError> {
+    use crate::schema::users::dsl::*;
+    tokio::spawn(async move {
+        let mut conn = pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
+        match users
+            .filter(id.eq(user_id))
+            .select(session_version)
+            .first::<i32>(&mut conn)
+            .await
+        {
+            // Short, so a revocation reaches the other instances within seconds.
+            Ok(version) => Ok((Some(version), Expiration::Short)),
+            Err(Error::NotFound) => Ok((None, Expiration::Short)),
+            Err(err) => Err(log_server_error(err, "cache", ERROR_DB_CONNECTION)),
+        }
+    })
+}
+
 impl Cache {
     // 初始化
-    pub fn new(pool: Arc<DbPool>) -> Self {
+    pub fn new(pool: Arc<DbPool>, capacity: &CacheConfig) -> Self {
         let fetch_closure_unlock = {
             let pool = Arc::clone(&pool);
             Box::new(move |key| fetchdb_unlock_level(Arc::clone(&pool), key))
@@ -250,33 +304,73 @@ impl Cache {
             Box::new(move |key| fetchdb_decipher(Arc::clone(&pool), key))
         };
 
+        let fetch_closure_session_version = {
+            let pool = Arc::clone(&pool);
+            Box::new(move |key| fetchdb_session_version(Arc::clone(&pool), key))
+        };
+
         let fetch_closure_time_punish = {
             let pool = Arc::clone(&pool);
             Box::new(move |key| fetchdb_time_punish(Arc::clone(&pool), key))
         };
 
         Self {
-            unlock_cache: AutoCache::new(4096, fetch_closure_unlock, write_closure_unlock),
+            unlock_cache: AutoCache::new(
+                "unlock",
+                capacity.unlock,
+                fetch_closure_unlock,
+                write_closure_unlock,
+            ),
             puzzle_cache: AutoCache::new(
-                32,
+                "puzzle",
+                capacity.puzzle,
                 fetch_closure_puzzle,
                 Box::new(|_, _| unimplemented!()), // Never write a puzzle
             ),
             time_punish_cache: AutoCache::new(
-                4096,
+                "time_punish",
+                capacity.time_punish,
                 fetch_closure_time_punish,
                 Box::new(|_, _| tokio::spawn(async { Ok(()) })), // Is written otherwise
             ),
             decipher_cache: AutoCache::new(
-                256,
+                "decipher",
+                capacity.decipher,
                 fetch_closure_decipher,
                 Box::new(|_, _| unimplemented!()), // Never write a puzzle
             ),
+            // Other instances notice a bumped version once it expires, in 2 seconds.
+            session_version_cache: AutoCache::new(
+                "session_version",
+                capacity.session_version,
+                fetch_closure_session_version,
+                Box::new(|_, _| tokio::spawn(async { Ok(()) })), // Is written otherwise
+            ),
 
             stat: MokaCache::builder()
                 .max_capacity(2)
                 .expire_after(MyExpiry)
                 .build(),
+            solve_stat: MokaCache::builder()
+                .max_capacity(2)
+                .expire_after(MyExpiry)
+                .build(),
+            activity: MokaCache::builder()
+                .max_capacity(64)
+                .expire_after(MyExpiry)
+                .build(),
+            puzzle_graph: MokaCache::builder()
+                .max_capacity(2)
+                .expire_after(MyExpiry)
+                .build(),
+            game_phase: MokaCache::builder()
+                .max_capacity(2)
+                .expire_after(MyExpiry)
+                .build(),
+            consumed_token: MokaCache::builder()
+                .max_capacity(capacity.consumed_token)
+                .time_to_live(std::time::Duration::from_secs(86400))
+                .build(),
             pool: pool.clone(),
         }
     }
@@ -287,11 +381,16 @@ impl Cache {
             puzzle: self.puzzle_cache.size(),
             time_punish: self.time_punish_cache.size(),
             decipher: self.decipher_cache.size(),
+            session_version: self.session_version_cache.size(),
+            consumed_token: self.consumed_token.entry_count(),
         }
     }
 
+    // The summary is cheap to read, so live counts are kept for a moment only,
+    // while a frozen scoreboard does not change until the phase does.
     pub async fn get_stat(&self) -> Result<Arc<PuzzleStatistic>, APIError> {
-        if let Some((_, data)) = self.stat.get(&()).await {
+        let frozen_at = self.get_game_phase().await?.frozen_at();
+        if let Some((_, data)) = self.stat.get(&frozen_at).await {
             return Ok(data);
         }
         let mut conn = self
@@ -299,8 +398,80 @@ impl Cache {
             .get()
             .await
             .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
-        let new_data = Arc::new(fetch_statistic(&mut conn).await?);
+        let (new_data, expiration) = match frozen_at {
+            Some(time) => (
+                fetch_frozen_statistic(&mut conn, time).await?,
+                Expiration::Long,
+            ),
+            None => (fetch_statistic(&mut conn).await?, Expiration::Short),
+        };
+        let new_data = Arc::new(new_data);
         self.stat
+            .get_with(frozen_at, async { (expiration, new_data.clone()) })
+            .await;
+        Ok(new_data)
+    }
+
+    pub async fn get_solve_stat(&self) -> Result<Arc<SolveStatistic>, APIError> {
+        if let Some((_, data)) = self.solve_stat.get(&()).await {
+            return Ok(data);
+        }
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
+        let new_data = Arc::new(fetch_solve_statistic(&mut conn).await?);
+        self.solve_stat
+            .get_with((), async { (Expiration::Middle, new_data.clone()) })
+            .await;
+        Ok(new_data)
+    }
+
+    pub async fn get_activity(&self, filter: ActivityFilter) -> Result<Arc<Activity>, APIError> {
+        if let Some((_, data)) = self.activity.get(&filter).await {
+            return Ok(data);
+        }
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
+        let new_data = Arc::new(fetch_activity(&mut conn, filter).await?);
+        self.activity
+            .get_with(filter, async { (Expiration::Middle, new_data.clone()) })
+            .await;
+        Ok(new_data)
+    }
+
+    // Other instances notice a switch within `Expiration::Short`.
+    pub async fn get_game_phase(&self) -> Result<PhaseState, APIError> {
+        if let Some((_, data)) = self.game_phase.get(&()).await {
+            return Ok(data);
+        }
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
+        let new_data = fetch_phase(&mut conn).await?;
+        self.game_phase
+            .get_with((), async { (Expiration::Short, new_data) })
+            .await;
+        Ok(new_data)
+    }
+
+    pub async fn get_puzzle_graph(&self) -> Result<Arc<PuzzleGraph>, APIError> {
+        if let Some((_, data)) = self.puzzle_graph.get(&()).await {
+            return Ok(data);
+        }
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| log_server_error(e, "cache", ERROR_DB_CONNECTION))?;
+        let new_data = Arc::new(fetch_graph(&mut conn).await?);
+        self.puzzle_graph
             .get_with((), async { (Expiration::Middle, new_data.clone()) })
             .await;
         Ok(new_data)
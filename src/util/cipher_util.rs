@@ -1,66 +1,195 @@
-use dotenv::dotenv;
-use once_cell::sync::Lazy;
-use serde::ser::SerializeStruct;
-use serde::Serialize;
+use crate::util::config::config;
+use crate::util::i18n::{t, t_args, Locale};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::env;
+use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-const EXPIRE_MIINUTES: u64 = 5;
+use utoipa::ToSchema;
+
+const DEFAULT_EXPIRE_MINUTES: u64 = 5;
+
+// The first raw byte of a v2 token. The first raw byte of a v1 token is
+// masked by its hash, so a v1 token may start with it by chance as well.
+const TOKEN_FORMAT_V2: u8 = 2;
 
 #[derive(Debug, Clone)]
 pub enum DecodeTokenError {
     Expired(Duration),
     InvalidContent(String),
     InvalidFormat,
+    // The key id of a v2 token is not (or no longer) active.
+    UnknownKey(u8),
+    // v1 tokens are not accepted after the transition deadline.
+    Deprecated,
+    // Each register token can only be used once.
+    Reused,
+    // Issued later than now, beyond the clock skew allowed.
+    FromFuture,
     Unknown,
 }
 
+/// A `DecodeTokenError` as sent to clients.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DecodeTokenErrorResponse {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    desp: String,
+}
+
+impl DecodeTokenError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DecodeTokenError::Expired(_) => "Expired",
+            DecodeTokenError::InvalidContent(_) => "InvalidContent",
+            DecodeTokenError::InvalidFormat => "InvalidFormat",
+            DecodeTokenError::UnknownKey(_) => "UnknownKey",
+            DecodeTokenError::Deprecated => "Deprecated",
+            DecodeTokenError::Reused => "Reused",
+            DecodeTokenError::FromFuture => "FromFuture",
+            DecodeTokenError::Unknown => "Unknown",
+        }
+    }
+
+    pub fn message(&self, locale: Locale) -> String {
+        match self {
+            DecodeTokenError::Expired(duration) => t_args(
+                locale,
+                "token.expired",
+                &[("seconds", duration.as_secs().to_string())],
+            ),
+            DecodeTokenError::InvalidContent(token) => {
+                t_args(locale, "token.invalid_content", &[("token", token.clone())])
+            }
+            DecodeTokenError::InvalidFormat => t(locale, "token.invalid_format"),
+            DecodeTokenError::UnknownKey(key_id) => t_args(
+                locale,
+                "token.unknown_key",
+                &[("key_id", key_id.to_string())],
+            ),
+            DecodeTokenError::Deprecated => t(locale, "token.deprecated"),
+            DecodeTokenError::Reused => t(locale, "token.reused"),
+            DecodeTokenError::FromFuture => t(locale, "token.from_future"),
+            DecodeTokenError::Unknown => t(locale, "token.unknown"),
+        }
+    }
+
+    pub fn localized(&self, locale: Locale) -> DecodeTokenErrorResponse {
+        DecodeTokenErrorResponse {
+            kind: self.kind(),
+            desp: self.message(locale),
+        }
+    }
+}
+
 impl Serialize for DecodeTokenError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("DecodeError", 2)?;
+        self.localized(Locale::default()).serialize(serializer)
+    }
+}
 
-        match self {
-            DecodeTokenError::Expired(duration) => {
-                state.serialize_field("type", "Expired")?;
-                state.serialize_field(
-                    "desp",
-                    format!("Expired {} seconds ago.", &duration.as_secs()).as_str(),
-                )?;
-            }
-            DecodeTokenError::InvalidContent(token) => {
-                state.serialize_field("type", "InvalidContent")?;
-                state.serialize_field(
-                    "desp",
-                    format!("Invalid register token {}.", &token).as_str(),
-                )?;
-            }
-            DecodeTokenError::InvalidFormat => {
-                state.serialize_field("type", "InvalidFormat")?;
-                state.serialize_field(
-                    "desp",
-                    "Register token should be a 64-digit hexadecimal number.",
-                )?;
-            }
-            DecodeTokenError::Unknown => {
-                state.serialize_field("type", "Unknown")?;
-                state.serialize_field("desp", "An unknown error occurred.")?;
-            }
+/// Secrets and policy for decoding register tokens, the `[register_token]`
+/// of the server config.
+/// - `v1_secret` (`REGISTER_TOKEN`): the secret of v1 tokens.
+/// - `keys` (`REGISTER_TOKENS` as `<key id>:<secret>,...`): active v2 keys.
+///   Add a key before issuing tokens with it, and remove it to retire it.
+/// - `v1_until` (`REGISTER_TOKEN_V1_UNTIL`): an RFC 3339 time after which v1
+///   tokens are rejected. v1 tokens are accepted indefinitely if it is not set.
+/// - `expire_minutes` (`REGISTER_TOKEN_EXPIRE_MINUTES`): lifetime of a token,
+///   5 by default.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegisterTokenKeys {
+    pub v1_secret: Option<String>,
+    pub v1_until: Option<DateTime<Utc>>,
+    #[serde(deserialize_with = "deserialize_key_ids")]
+    pub keys: HashMap<u8, String>,
+    pub expire_minutes: u64,
+}
+
+impl Default for RegisterTokenKeys {
+    fn default() -> Self {
+        Self {
+            v1_secret: None,
+            v1_until: None,
+            keys: HashMap::new(),
+            expire_minutes: DEFAULT_EXPIRE_MINUTES,
         }
+    }
+}
 
-        state.end()
+// Only the key ids, not the secrets.
+impl fmt::Debug for RegisterTokenKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut key_ids: Vec<&u8> = self.keys.keys().collect();
+        key_ids.sort();
+        f.debug_struct("RegisterTokenKeys")
+            .field("v1", &self.v1_secret.is_some())
+            .field("v1_until", &self.v1_until)
+            .field("key_ids", &key_ids)
+            .field("expire_minutes", &self.expire_minutes)
+            .finish()
     }
 }
 
-pub fn decode_token(hex_input: &str, token: &str) -> Result<(u8, u8, String), DecodeTokenError> {
-    let bytes = hex::decode(hex_input).map_err(|_| DecodeTokenError::InvalidFormat)?;
-    if bytes.len() != 64 {
-        return Err(DecodeTokenError::InvalidFormat);
+// TOML keys are strings.
+fn deserialize_key_ids<'de, D>(deserializer: D) -> Result<HashMap<u8, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key_id, secret)| {
+            key_id
+                .parse::<u8>()
+                .map(|key_id| (key_id, secret))
+                .map_err(|e| D::Error::custom(format!("key id {key_id}: {e}")))
+        })
+        .collect()
+}
+
+impl RegisterTokenKeys {
+    fn accepts_v1(&self) -> bool {
+        self.v1_secret.is_some() && self.v1_until.is_none_or(|until| Utc::now() < until)
     }
+}
+
+// Between the clocks of the token issuer and this server.
+const TOKEN_CLOCK_SKEW_SECONDS: u64 = 60;
+
+fn check_token_time(issued_at: u64, expire_minutes: u64) -> Result<(), DecodeTokenError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| DecodeTokenError::Unknown)?
+        .as_secs();
 
+    if issued_at > now + TOKEN_CLOCK_SKEW_SECONDS {
+        return Err(DecodeTokenError::FromFuture);
+    }
+    let age = now.saturating_sub(issued_at);
+    if age > expire_minutes * 60 {
+        return Err(DecodeTokenError::Expired(Duration::from_secs(
+            age - expire_minutes * 60,
+        )));
+    }
+    Ok(())
+}
+
+// v1 layout, 32 bytes XOR-masked by their own salted SHA256, then the hash:
+// version (8 bits), openid (168 bits), time in minutes (32 bits), mark (8 bits), nonce (40 bits)
+fn decode_token_v1(
+    bytes: &[u8],
+    hex_input: &str,
+    secret: &str,
+    expire_minutes: u64,
+) -> Result<(u8, u8, String), DecodeTokenError> {
     let (encoded_token, received_hash) = bytes.split_at(32);
 
     let decoded_token: Vec<u8> = encoded_token
@@ -69,7 +198,7 @@ pub fn decode_token(hex_input: &str, token: &str) -> Result<(u8, u8, String), De
         .map(|(&a, &b)| a ^ b)
         .collect();
 
-    let salt_bytes = token.as_bytes();
+    let salt_bytes = secret.as_bytes();
 
     let mut hasher = Sha256::new();
     hasher.update(&decoded_token);
@@ -90,22 +219,139 @@ pub fn decode_token(hex_input: &str, token: &str) -> Result<(u8, u8, String), De
         * 60;
     let mark = decoded_token[26];
 
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|_| DecodeTokenError::Unknown)?
-        .as_secs();
+    check_token_time(time, expire_minutes)?;
 
-    if now - time > EXPIRE_MIINUTES * 60 {
-        return Err(DecodeTokenError::Expired(Duration::from_secs(
-            now - time - EXPIRE_MIINUTES * 60,
-        )));
+    Ok((version, mark, openid))
+}
+
+fn token_mac(secret: &str, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    mac
+}
+
+// v2 layout, 32 plain bytes followed by their HMAC-SHA256:
+// format (8 bits), key id (8 bits), openid (168 bits), time in seconds (32 bits), mark (8 bits), nonce (32 bits)
+fn decode_token_v2(
+    bytes: &[u8],
+    hex_input: &str,
+    keys: &RegisterTokenKeys,
+) -> Result<(u8, u8, String), DecodeTokenError> {
+    let (payload, received_mac) = bytes.split_at(32);
+
+    let key_id = payload[1];
+    let secret = keys
+        .keys
+        .get(&key_id)
+        .ok_or(DecodeTokenError::UnknownKey(key_id))?;
+
+    token_mac(secret, payload)
+        .verify_slice(received_mac)
+        .map_err(|_| DecodeTokenError::InvalidContent(hex_input.to_owned()))?;
+
+    let openid = hex::encode(&payload[2..23]);
+    let time = u32::from_be_bytes(
+        payload[23..27]
+            .try_into()
+            .map_err(|_| DecodeTokenError::InvalidContent(hex_input.to_owned()))?,
+    ) as u64;
+    let mark = payload[27];
+
+    check_token_time(time, keys.expire_minutes)?;
+
+    Ok((TOKEN_FORMAT_V2, mark, openid))
+}
+
+/// Issues a v2 register token, the counterpart of `decode_token`.
+///
+/// ``` rust
+/// use std::collections::HashMap;
+/// use server::util::cipher_util::{decode_token, encode_token_v2, RegisterTokenKeys};
+///
+/// let mut keys = RegisterTokenKeys {
+///     v1_secret: None,
+///     v1_until: None,
+///     keys: HashMap::from([(7, "secret".to_string())]),
+///     expire_minutes: 5,
+/// };
+/// let openid = [0x5au8; 21];
+///
+/// let token = encode_token_v2(7, "secret", &openid, 4).unwrap();
+/// assert_eq!(token.len(), 128);
+/// assert_eq!(decode_token(&token, &keys).unwrap(), (2, 4, hex::encode(openid)));
+///
+/// // Tampered tokens are rejected.
+/// let mut tampered = token.clone();
+/// tampered.replace_range(60..62, "ff");
+/// assert!(decode_token(&tampered, &keys).is_err());
+///
+/// // Retired keys are rejected.
+/// keys.keys.clear();
+/// assert!(decode_token(&token, &keys).is_err());
+/// ```
+pub fn encode_token_v2(key_id: u8, secret: &str, openid: &[u8; 21], mark: u8) -> Option<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+    let mut payload = Vec::with_capacity(64);
+    payload.push(TOKEN_FORMAT_V2);
+    payload.push(key_id);
+    payload.extend_from_slice(openid);
+    payload.extend_from_slice(&u32::try_from(now).ok()?.to_be_bytes());
+    payload.push(mark);
+    payload.extend_from_slice(&get_salt::<4>());
+
+    let mac = token_mac(secret, &payload).finalize().into_bytes();
+    payload.extend_from_slice(&mac);
+    Some(hex::encode(payload))
+}
+
+/// Identifies a register token regardless of the case of its hex digits.
+pub fn hash_register_token(hex_input: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(hex_input.to_ascii_lowercase());
+    hex::encode(hasher.finalize())
+}
+
+/// Returns the version, mark and openid of a register token.
+pub fn decode_token(
+    hex_input: &str,
+    keys: &RegisterTokenKeys,
+) -> Result<(u8, u8, String), DecodeTokenError> {
+    let bytes = hex::decode(hex_input).map_err(|_| DecodeTokenError::InvalidFormat)?;
+    if bytes.len() != 64 {
+        return Err(DecodeTokenError::InvalidFormat);
     }
 
-    Ok((version, mark, openid))
+    let v1_secret = keys.v1_secret.as_deref().filter(|_| keys.accepts_v1());
+
+    if bytes[0] == TOKEN_FORMAT_V2 {
+        match (decode_token_v2(&bytes, hex_input, keys), v1_secret) {
+            // Maybe a v1 token starting with the format byte by chance.
+            (
+                Err(e @ (DecodeTokenError::UnknownKey(_) | DecodeTokenError::InvalidContent(_))),
+                Some(secret),
+            ) => decode_token_v1(&bytes, hex_input, secret, keys.expire_minutes).map_err(
+                |v1_error| match v1_error {
+                    DecodeTokenError::InvalidContent(_) => e,
+                    v1_error => v1_error,
+                },
+            ),
+            (result, _) => result,
+        }
+    } else {
+        match v1_secret {
+            Some(secret) => decode_token_v1(&bytes, hex_input, secret, keys.expire_minutes),
+            None if keys.v1_secret.is_some() => Err(DecodeTokenError::Deprecated),
+            None => Err(DecodeTokenError::InvalidContent(hex_input.to_owned())),
+        }
+    }
 }
 
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
 use rand::rngs::OsRng;
-use rand::RngCore;
+use rand::{Rng, RngCore};
 
 pub fn get_salt<const N: usize>() -> [u8; N] {
     let mut salt = [0u8; N];
@@ -113,40 +359,174 @@ pub fn get_salt<const N: usize>() -> [u8; N] {
     salt
 }
 
-pub fn gen_salted_password(password: &str, token: &str) -> (String, String) {
+pub const PASSWORD_SCHEME_LEGACY: i32 = 0;
+pub const PASSWORD_SCHEME_ARGON2ID: i32 = 1;
+
+// OWASP recommended minimum for Argon2id: 19 MiB, 2 iterations, 1 lane.
+const ARGON2_M_COST: u32 = 19 * 1024;
+const ARGON2_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
+
+fn argon2_params() -> Params {
+    Params::new(ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST, None)
+        .expect("Invalid Argon2 parameters")
+}
+
+// The login token is used as the Argon2 secret (pepper).
+fn argon2_hasher(token: &str) -> Option<Argon2<'_>> {
+    Argon2::new_with_secret(
+        token.as_bytes(),
+        Algorithm::Argon2id,
+        Version::V0x13,
+        argon2_params(),
+    )
+    .ok()
+}
+
+/// Returns `(salt, hash, scheme)` where `hash` is an Argon2id PHC string,
+/// which carries its own parameters.
+/// CAVEAT: Deliberately slow, run it on a blocking thread.
+///
+/// ``` rust
+/// use server::models::User;
+/// use server::util::cipher_util::{check_salted_password, gen_salted_password, password_needs_rehash};
+///
+/// let (salt, password, password_scheme) = gen_salted_password("pw", "token");
+/// let user = User {
+///     id: 1,
+///     openid: String::new(),
+///     team: None,
+///     username: String::new(),
+///     password,
+///     salt,
+///     privilege: 0,
+///     password_scheme,
+///     session_version: 0,
+///     totp_secret: None,
+///     totp_enabled_at: None,
+///     totp_last_step: 0,
+///     locale: None,
+/// };
+/// assert!(check_salted_password(&user, "pw", "token").is_some());
+/// assert!(check_salted_password(&user, "pw", "another token").is_none());
+/// assert!(check_salted_password(&user, "wrong", "token").is_none());
+/// assert!(!password_needs_rehash(&user));
+/// ```
+pub fn gen_salted_password(password: &str, token: &str) -> (String, String, i32) {
     let salt = get_salt::<32>();
+    let salt_string = SaltString::encode_b64(&salt).expect("Salt of 32 bytes is always valid");
+
+    let hash = argon2_hasher(token)
+        .expect("Login token too long to be an Argon2 secret")
+        .hash_password(password.as_bytes(), &salt_string)
+        .expect("Argon2 hashing failed")
+        .to_string();
+
+    (hex::encode(salt), hash, PASSWORD_SCHEME_ARGON2ID)
+}
 
+fn check_legacy_password(user: &User, password_input: &str, token: &str) -> Option<()> {
+    let mut salt = [0u8; 32];
+    hex::decode_to_slice(&user.salt, &mut salt).ok()?;
     let mut hasher = Sha256::new();
     hasher.update(token);
-    hasher.update(password);
+    hasher.update(password_input);
     hasher.update(salt);
+
     let calculated_hash = hasher.finalize();
 
-    (hex::encode(salt), hex::encode(calculated_hash.as_slice()))
+    let mut expected_hash = [0u8; 32];
+    hex::decode_to_slice(&user.password, &mut expected_hash).ok()?;
+
+    (calculated_hash.as_slice() == &expected_hash[..]).then_some(())
 }
 
+fn check_argon2_password(user: &User, password_input: &str, token: &str) -> Option<()> {
+    let hash = PasswordHash::new(&user.password).ok()?;
+    argon2_hasher(token)?
+        .verify_password(password_input.as_bytes(), &hash)
+        .ok()
+}
+
+/// Verifies both legacy SHA-256 and Argon2id hashes.
+/// CAVEAT: Deliberately slow, run it on a blocking thread.
 pub fn check_salted_password<'a>(
     user: &'a User,
     password_input: &str,
     token: &str,
 ) -> Option<&'a User> {
-    let mut salt = [0u8; 32];
-    hex::decode_to_slice(&user.salt, &mut salt).ok()?;
+    match user.password_scheme {
+        PASSWORD_SCHEME_LEGACY => check_legacy_password(user, password_input, token),
+        PASSWORD_SCHEME_ARGON2ID => check_argon2_password(user, password_input, token),
+        _ => None,
+    }
+    .map(|()| user)
+}
+
+/// Whether the stored hash should be replaced after a successful login,
+/// either because it is a legacy hash or because its parameters are outdated.
+pub fn password_needs_rehash(user: &User) -> bool {
+    if user.password_scheme != PASSWORD_SCHEME_ARGON2ID {
+        return true;
+    }
+    match PasswordHash::new(&user.password)
+        .ok()
+        .and_then(|hash| Params::try_from(&hash).ok())
+    {
+        Some(params) => {
+            params.m_cost() != ARGON2_M_COST
+                || params.t_cost() != ARGON2_T_COST
+                || params.p_cost() != ARGON2_P_COST
+        }
+        None => true,
+    }
+}
+
+/// A 6-digit code to be delivered by mail.
+pub fn gen_one_time_code() -> String {
+    format!("{:06}", OsRng.gen_range(0..1_000_000))
+}
+
+pub fn hash_one_time_code(code: &str, email: &str, token: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(token);
-    hasher.update(password_input);
-    hasher.update(salt);
+    hasher.update(email);
+    hasher.update(code);
+    hex::encode(hasher.finalize().as_slice())
+}
 
-    let calculated_hash = hasher.finalize();
+pub fn hash_invite_token(invite_token: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token);
+    hasher.update("invite");
+    hasher.update(invite_token);
+    hex::encode(hasher.finalize())
+}
 
-    let mut expected_hash = [0u8; 32];
-    hex::decode_to_slice(&user.password, &mut expected_hash).ok()?;
+/// Whether `given` is the secret `token`. Their HMACs under the token are
+/// compared in constant time, so timing tells neither prefix nor length.
+pub fn check_bearer_token(given: &str, token: &str) -> bool {
+    let expected = token_mac(token, token.as_bytes()).finalize().into_bytes();
+    token_mac(token, given.as_bytes())
+        .verify_slice(&expected)
+        .is_ok()
+}
 
-    if calculated_hash.as_slice() == &expected_hash[..] {
-        Some(user)
-    } else {
-        None
-    }
+pub fn hash_reset_token(reset_token: &str, token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token);
+    hasher.update(reset_token);
+    hex::encode(hasher.finalize().as_slice())
+}
+
+/// Users signed up by email have no WeChat openid, so a stable one is
+/// derived from the address.
+pub fn email_openid(email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(email);
+    let mut openid = format!("mail{}", hex::encode(hasher.finalize().as_slice()));
+    openid.truncate(64);
+    openid
 }
 
 use actix_web::cookie::Key;
@@ -159,11 +539,6 @@ pub fn gen_cookie_key(cookie_token: &str) -> Key {
     Key::from(hasher.finalize().as_slice())
 }
 
-static VERIFY_TOKEN: Lazy<String> = Lazy::new(|| {
-    dotenv().ok();
-    env::var("VERIFY_TOKEN").expect("Environment variable VERIFY_TOKEN not set")
-});
-
 fn current_totp_time() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -175,7 +550,7 @@ fn current_totp_time() -> u64 {
 fn totp(token_id: &str, time: u64) -> String {
     let mut hasher = Sha512::new();
     hasher.update(token_id);
-    hasher.update(VERIFY_TOKEN.as_str());
+    hasher.update(config().secrets.verify_token.as_str());
     hasher.update(time.to_le_bytes());
 
     hex::encode(hasher.finalize().as_slice())
@@ -197,6 +572,109 @@ pub fn verify_totp(identity: &str, veri_code: &str) -> bool {
             || totp(identity, time + 1).starts_with(veri_code))
 }
 
+// RFC 6238 authenticator apps: HMAC-SHA1, 6 digits, 30-second steps.
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// Accepted steps before and after the current one.
+const TOTP_DRIFT_STEPS: u64 = 1;
+const TOTP_ISSUER: &str = "Yuanyang";
+
+/// HOTP (RFC 4226), the building block of TOTP (RFC 6238).
+///
+/// ``` rust
+/// use server::util::cipher_util::hotp;
+///
+/// // Test vectors of RFC 6238, Appendix B (SHA1).
+/// let key = b"12345678901234567890";
+/// assert_eq!(hotp(key, 59 / 30, 8), "94287082");
+/// assert_eq!(hotp(key, 1111111109 / 30, 8), "07081804");
+/// assert_eq!(hotp(key, 1111111111 / 30, 8), "14050471");
+/// assert_eq!(hotp(key, 1234567890 / 30, 8), "89005924");
+/// assert_eq!(hotp(key, 2000000000 / 30, 8), "69279037");
+/// assert_eq!(hotp(key, 20000000000 / 30, 8), "65353130");
+/// ```
+pub fn hotp(key: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(
+        hash[offset..offset + 4]
+            .try_into()
+            .expect("HMAC-SHA1 is 20 bytes long"),
+    ) & 0x7fff_ffff;
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// A random 160-bit TOTP secret in base32, as authenticator apps expect.
+pub fn gen_totp_secret() -> String {
+    data_encoding::BASE32_NOPAD.encode(&get_salt::<20>())
+}
+
+/// The `otpauth://` URI to be shown as a QR code when enrolling.
+pub fn totp_uri(secret: &str, account: &str) -> String {
+    let encode = |s: &str| -> String {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{b:02X}"),
+            })
+            .collect()
+    };
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_STEP_SECONDS}",
+        issuer = encode(TOTP_ISSUER),
+        account = encode(account),
+    )
+}
+
+/// Returns the matched time step if `code` is valid for the secret within the
+/// drift window and newer than `last_step`, so each code is only accepted once.
+pub fn verify_rfc6238(secret: &str, code: &str, last_step: i64) -> Option<i64> {
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let key = data_encoding::BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let step = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() / TOTP_STEP_SECONDS;
+
+    (step.saturating_sub(TOTP_DRIFT_STEPS)..=step + TOTP_DRIFT_STEPS)
+        .filter(|candidate| *candidate as i64 > last_step)
+        .find(|candidate| hotp(&key, *candidate, TOTP_DIGITS) == code)
+        .map(|candidate| candidate as i64)
+}
+
+/// A recovery code like `k3f9q-x7m2d`, the second factor if the device is lost.
+pub fn gen_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+pub fn hash_recovery_code(code: &str, token: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let mut hasher = Sha256::new();
+    hasher.update(token);
+    hasher.update("recovery");
+    hasher.update(normalized);
+    hex::encode(hasher.finalize())
+}
+
 pub fn check_answer(answer: &str, key: &str, submission: &str) -> bool {
     let mut hasher = Sha256::new();
     hasher.update(key);
@@ -0,0 +1,569 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use actix_web::cookie::SameSite;
+use chrono::{DateTime, Utc};
+use lettre::message::Mailbox;
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use super::cipher_util::RegisterTokenKeys;
+
+pub const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+/// `SERVER_CONFIG`, or `server.toml`.
+pub fn config_path() -> String {
+    std::env::var("SERVER_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+}
+
+static CONFIG: OnceCell<ServerConfig> = OnceCell::new();
+
+/// Settings of the server, read from `server.toml` (or the file given by
+/// `SERVER_CONFIG`) and then overridden by the environment. Secrets are
+/// better left to the environment.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub http: HttpConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub game: GameConfig,
+    pub mail: MailConfig,
+    pub secrets: SecretsConfig,
+    pub register_token: RegisterTokenKeys,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// `BIND_ADDRESS`
+    pub bind: SocketAddr,
+    /// `WORKERS`, one per physical CPU core if not set.
+    pub workers: Option<usize>,
+    /// `MODE`, which is `dev` for a development server. Cookies are only
+    /// sent over HTTPS in production.
+    pub production: bool,
+    /// `CORS_ORIGINS`, comma separated.
+    pub cors_origins: Vec<String>,
+    /// Origins ending with one of these are allowed as well, e.g. the deploy
+    /// previews of the front-end.
+    pub cors_origin_suffixes: Vec<String>,
+    /// `COOKIE_SAME_SITE`, `None` in production and `Lax` otherwise if not set.
+    pub cookie_same_site: Option<CookieSameSite>,
+    /// `RATE_LIMIT_CONFIG`
+    pub rate_limit_config: String,
+    /// `TRUSTED_PROXIES`, comma separated. `X-Forwarded-For` is only believed
+    /// when the peer is one of them.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 9000)),
+            workers: None,
+            production: true,
+            cors_origins: vec![
+                "https://2025.yuanyang.app".to_string(),
+                "https://yuanyang.app".to_string(),
+                "http://localhost:5173".to_string(),
+            ],
+            cors_origin_suffixes: vec!["yuanyang25-front.netlify.app".to_string()],
+            cookie_same_site: None,
+            rate_limit_config: "rate_limit.toml".to_string(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl HttpConfig {
+    /// ``` rust
+    /// use server::util::config::HttpConfig;
+    ///
+    /// let config = HttpConfig::default();
+    /// assert!(config.allows_origin("https://yuanyang.app"));
+    /// assert!(config.allows_origin("https://pr-1--yuanyang25-front.netlify.app"));
+    /// assert!(!config.allows_origin("https://yuanyang.app.example.com"));
+    /// ```
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.cors_origins.iter().any(|allowed| allowed == origin)
+            || self
+                .cors_origin_suffixes
+                .iter()
+                .any(|suffix| origin.ends_with(suffix.as_str()))
+    }
+
+    pub fn same_site(&self) -> SameSite {
+        match self.cookie_same_site {
+            Some(CookieSameSite::Strict) => SameSite::Strict,
+            Some(CookieSameSite::Lax) => SameSite::Lax,
+            Some(CookieSameSite::None) => SameSite::None,
+            // Cross-site requests from the front-end need `None`.
+            None if self.production => SameSite::None,
+            None => SameSite::Lax,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for CookieSameSite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(CookieSameSite::Strict),
+            "lax" => Ok(CookieSameSite::Lax),
+            "none" => Ok(CookieSameSite::None),
+            _ => Err(format!("expected strict, lax or none, got {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `DATABASE_URL`
+    pub url: String,
+    /// `DB_POOL_SIZE`
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            pool_size: 10,
+        }
+    }
+}
+
+// The url contains the password.
+impl fmt::Debug for DatabaseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseConfig")
+            .field("pool_size", &self.pool_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Max number of entries of each cache.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub unlock: usize,
+    pub puzzle: usize,
+    pub decipher: usize,
+    pub time_punish: usize,
+    pub session_version: usize,
+    pub consumed_token: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            unlock: 4096,
+            puzzle: 32,
+            decipher: 256,
+            time_punish: 4096,
+            session_version: 4096,
+            consumed_token: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    /// `GAME_EPOCH`, in RFC 3339. Prices and awards change with the time
+    /// since then.
+    pub epoch: DateTime<Utc>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            epoch: "2025-01-29T12:00:00Z".parse().unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// `MAIL_TRANSPORT`
+    pub transport: MailTransport,
+    /// `MAIL_SPOOL_DIR`, where the spool transport writes the mails.
+    pub spool_dir: String,
+    /// `SMTP_HOST`, required by the smtp transport.
+    pub smtp_host: String,
+    /// `SMTP_PORT`
+    pub smtp_port: u16,
+    /// `SMTP_STARTTLS`, STARTTLS (usually port 587) instead of implicit TLS
+    /// (usually port 465).
+    pub smtp_starttls: bool,
+    /// `SMTP_USERNAME`, authenticating only if set.
+    pub smtp_username: Option<String>,
+    /// `SMTP_PASSWORD`
+    pub smtp_password: String,
+    /// `MAIL_FROM`, the sender of the smtp transport.
+    pub from: String,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            transport: MailTransport::Spool,
+            spool_dir: "mail_spool".to_string(),
+            smtp_host: String::new(),
+            smtp_port: 465,
+            smtp_starttls: false,
+            smtp_username: None,
+            smtp_password: String::new(),
+            from: String::new(),
+        }
+    }
+}
+
+// The password is a secret.
+impl fmt::Debug for MailConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MailConfig")
+            .field("transport", &self.transport)
+            .field("spool_dir", &self.spool_dir)
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_starttls", &self.smtp_starttls)
+            .field("smtp_username", &self.smtp_username)
+            .field("from", &self.from)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Files in `spool_dir`, for local testing.
+    Spool,
+    /// The SMTP relay `smtp_host`.
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "spool" => Ok(MailTransport::Spool),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => Err(format!("expected spool or smtp, got {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    /// `COOKIE_TOKEN`, from which the key of the session cookies is derived.
+    pub cookie_token: String,
+    /// `LOGIN_TOKEN`, salting passwords and hashing one-time codes.
+    pub login_token: String,
+    /// `VERIFY_TOKEN`, deriving the verification codes of teams.
+    pub verify_token: String,
+    /// `METRICS_TOKEN`, the bearer token of `/metrics` for scrapers without a
+    /// session, which must not be empty. Only admins can read the metrics if
+    /// not set.
+    pub metrics_token: Option<String>,
+}
+
+impl fmt::Debug for SecretsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretsConfig").finish_non_exhaustive()
+    }
+}
+
+/// The variable `name` parsed, if it is set.
+fn parse_env<T>(name: &str, errors: &mut Vec<String>) -> Option<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = std::env::var(name).ok()?;
+    value
+        .trim()
+        .parse()
+        .map_err(|e| errors.push(format!("{name}: {e}")))
+        .ok()
+}
+
+fn override_with<T>(name: &str, target: &mut T, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = parse_env(name, errors) {
+        *target = value;
+    }
+}
+
+fn override_with_some<T>(name: &str, target: &mut Option<T>, errors: &mut Vec<String>)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = parse_env(name, errors) {
+        *target = Some(value);
+    }
+}
+
+/// Parses the `<key id>:<secret>,...` of `REGISTER_TOKENS`.
+fn parse_register_tokens(list: &str) -> Result<HashMap<u8, String>, String> {
+    list.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (key_id, secret) = entry
+                .trim()
+                .split_once(':')
+                .ok_or_else(|| format!("REGISTER_TOKENS: expected id:secret, got {entry}"))?;
+            let key_id = key_id
+                .parse::<u8>()
+                .map_err(|e| format!("REGISTER_TOKENS: key id {key_id}: {e}"))?;
+            Ok((key_id, secret.to_string()))
+        })
+        .collect()
+}
+
+impl ServerConfig {
+    /// Reads `path`, which may not exist, applies the environment and
+    /// validates the result. All the problems found are reported at once.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut config = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| format!("{path}: {e}"))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Server config {path} not found, using defaults and the environment.");
+                Self::default()
+            }
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+
+        dotenv::dotenv().ok();
+        let mut errors = config.apply_env();
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!(
+                "Invalid server configuration:\n  {}",
+                errors.join("\n  ")
+            ))
+        }
+    }
+
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut errors = vec![];
+
+        let http = &mut self.http;
+        override_with("BIND_ADDRESS", &mut http.bind, &mut errors);
+        override_with_some("WORKERS", &mut http.workers, &mut errors);
+        if let Ok(mode) = std::env::var("MODE") {
+            http.production = mode != "dev";
+        }
+        if let Ok(origins) = std::env::var("CORS_ORIGINS") {
+            http.cors_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        override_with_some("COOKIE_SAME_SITE", &mut http.cookie_same_site, &mut errors);
+        override_with(
+            "RATE_LIMIT_CONFIG",
+            &mut http.rate_limit_config,
+            &mut errors,
+        );
+        if let Ok(proxies) = std::env::var("TRUSTED_PROXIES") {
+            http.trusted_proxies = proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .filter_map(|proxy| {
+                    proxy
+                        .parse()
+                        .map_err(|e| errors.push(format!("TRUSTED_PROXIES: {proxy}: {e}")))
+                        .ok()
+                })
+                .collect();
+        }
+
+        override_with("DATABASE_URL", &mut self.database.url, &mut errors);
+        override_with("DB_POOL_SIZE", &mut self.database.pool_size, &mut errors);
+
+        override_with("GAME_EPOCH", &mut self.game.epoch, &mut errors);
+
+        let mail = &mut self.mail;
+        override_with("MAIL_TRANSPORT", &mut mail.transport, &mut errors);
+        override_with("MAIL_SPOOL_DIR", &mut mail.spool_dir, &mut errors);
+        override_with("SMTP_HOST", &mut mail.smtp_host, &mut errors);
+        override_with("SMTP_PORT", &mut mail.smtp_port, &mut errors);
+        override_with("SMTP_STARTTLS", &mut mail.smtp_starttls, &mut errors);
+        override_with_some("SMTP_USERNAME", &mut mail.smtp_username, &mut errors);
+        override_with("SMTP_PASSWORD", &mut mail.smtp_password, &mut errors);
+        override_with("MAIL_FROM", &mut mail.from, &mut errors);
+
+        let secrets = &mut self.secrets;
+        override_with("COOKIE_TOKEN", &mut secrets.cookie_token, &mut errors);
+        override_with("LOGIN_TOKEN", &mut secrets.login_token, &mut errors);
+        override_with("VERIFY_TOKEN", &mut secrets.verify_token, &mut errors);
+        override_with_some("METRICS_TOKEN", &mut secrets.metrics_token, &mut errors);
+
+        let register_token = &mut self.register_token;
+        override_with_some("REGISTER_TOKEN", &mut register_token.v1_secret, &mut errors);
+        if let Ok(list) = std::env::var("REGISTER_TOKENS") {
+            match parse_register_tokens(&list) {
+                Ok(keys) => register_token.keys = keys,
+                Err(e) => errors.push(e),
+            }
+        }
+        override_with_some(
+            "REGISTER_TOKEN_V1_UNTIL",
+            &mut register_token.v1_until,
+            &mut errors,
+        );
+        override_with(
+            "REGISTER_TOKEN_EXPIRE_MINUTES",
+            &mut register_token.expire_minutes,
+            &mut errors,
+        );
+
+        errors
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_string());
+            }
+        };
+
+        check(
+            self.http.workers != Some(0),
+            "http.workers (WORKERS) must be positive",
+        );
+        check(
+            self.http
+                .cors_origins
+                .iter()
+                .all(|origin| origin.starts_with("https://") || origin.starts_with("http://")),
+            "http.cors_origins (CORS_ORIGINS) must be http(s) origins",
+        );
+        check(
+            !self.database.url.is_empty(),
+            "database.url (DATABASE_URL) must be set",
+        );
+        check(
+            self.database.pool_size > 0,
+            "database.pool_size (DB_POOL_SIZE) must be positive",
+        );
+        let cache = &self.cache;
+        check(
+            [
+                cache.unlock,
+                cache.puzzle,
+                cache.decipher,
+                cache.time_punish,
+                cache.session_version,
+            ]
+            .iter()
+            .all(|capacity| *capacity > 0)
+                && cache.consumed_token > 0,
+            "cache capacities must be positive",
+        );
+        let mail = &self.mail;
+        if mail.transport == MailTransport::Smtp {
+            check(
+                !mail.smtp_host.is_empty(),
+                "mail.smtp_host (SMTP_HOST) must be set for the smtp transport",
+            );
+            check(
+                mail.from.parse::<Mailbox>().is_ok(),
+                "mail.from (MAIL_FROM) must be a mailbox for the smtp transport",
+            );
+        } else {
+            check(
+                !mail.spool_dir.is_empty(),
+                "mail.spool_dir (MAIL_SPOOL_DIR) must be set for the spool transport",
+            );
+        }
+        check(
+            !self.secrets.cookie_token.is_empty(),
+            "secrets.cookie_token (COOKIE_TOKEN) must be set",
+        );
+        check(
+            !self.secrets.login_token.is_empty(),
+            "secrets.login_token (LOGIN_TOKEN) must be set",
+        );
+        check(
+            !self.secrets.verify_token.is_empty(),
+            "secrets.verify_token (VERIFY_TOKEN) must be set",
+        );
+        check(
+            self.secrets
+                .metrics_token
+                .as_deref()
+                .is_none_or(|token| !token.is_empty()),
+            "secrets.metrics_token (METRICS_TOKEN) must not be empty",
+        );
+        let register_token = &self.register_token;
+        check(
+            register_token.v1_secret.is_some() || !register_token.keys.is_empty(),
+            "register_token: neither REGISTER_TOKEN nor REGISTER_TOKENS is set",
+        );
+        // Tokens signed with an empty secret could be forged by anyone.
+        check(
+            register_token
+                .v1_secret
+                .as_deref()
+                .is_none_or(|secret| !secret.is_empty()),
+            "register_token.v1_secret (REGISTER_TOKEN) must not be empty",
+        );
+        check(
+            register_token
+                .keys
+                .values()
+                .all(|secret| !secret.is_empty()),
+            "register_token.keys (REGISTER_TOKENS) must not have an empty secret",
+        );
+        check(
+            self.register_token.expire_minutes > 0,
+            "register_token.expire_minutes (REGISTER_TOKEN_EXPIRE_MINUTES) must be positive",
+        );
+
+        errors
+    }
+
+    /// Makes this the configuration returned by `config()`. Fails if `config()`
+    /// has been called already.
+    pub fn install(self) -> Result<(), String> {
+        CONFIG
+            .set(self)
+            .map_err(|_| "The server configuration is installed already".to_string())
+    }
+}
+
+/// The installed configuration, or the one loaded from `SERVER_CONFIG` if none
+/// is, e.g. in tests.
+pub fn config() -> &'static ServerConfig {
+    CONFIG.get_or_init(|| ServerConfig::load(&config_path()).unwrap_or_else(|e| panic!("{e}")))
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use diesel::prelude::*;
 use diesel::result::DatabaseErrorKind;
 use diesel::result::Error as DieselError;
@@ -6,14 +6,11 @@ use diesel::QueryDsl;
 use diesel_async::AsyncPgConnection;
 use diesel_async::RunQueryDsl;
 use log::debug;
-use once_cell::sync::Lazy;
 use std::cmp::max;
-use std::env;
 use std::ops::DerefMut;
 
-use dotenv::dotenv;
-
 use super::api_util::{new_unlocated_server_error, APIError};
+use super::config::config;
 
 #[derive(Debug)]
 pub enum UpdateBalanceError {
@@ -66,7 +63,8 @@ where
 }
 
 /// Attempts to modify the team's token balance and logs the transaction.
-/// CAVEAT: Always used within a sql transaction!
+/// CAVEAT: Always used within a sql transaction! The caller counts the tokens
+/// with `metrics().count_tokens` once it is committed.
 async fn modify_team_balance<C>(
     team_id: i32,
     amount: i64,
@@ -128,17 +126,12 @@ where
     Ok(new_balance + time_allowance)
 }
 
-static GAME_EPOCH: Lazy<DateTime<Utc>> = Lazy::new(|| {
-    dotenv().ok();
-    env::var("GAME_EPOCH")
-        .map(|time_str| time_str.as_str().parse::<DateTime<Utc>>().ok())
-        .ok()
-        .flatten()
-        .unwrap_or_else(|| "2025-01-29T12:00:00Z".parse::<DateTime<Utc>>().unwrap())
-});
+/// Every wrong answer is logged as a transaction described by this and the
+/// puzzle id, even when it is free, which `/staff_activity` counts.
+pub const WRONG_ANSWER_DESP: &str = "Wrong answer penalty puzzle ";
 
 pub fn game_start_minutes() -> f64 {
-    let diff = Utc::now() - GAME_EPOCH.to_utc();
+    let diff = Utc::now() - config().game.epoch;
     max(0, diff.num_seconds()) as f64 / 60.0
 }
 
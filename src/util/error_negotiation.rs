@@ -0,0 +1,59 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, ContentType};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpRequest, HttpResponse};
+
+use super::api_util::APIError;
+use super::i18n::Locale;
+
+// Only clients asking for JSON explicitly get it, `*/*` is what old clients
+// (e.g. a plain `fetch`) send.
+fn accepts_json(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| {
+            let media = media.split(';').next().unwrap_or_default().trim();
+            media.eq_ignore_ascii_case("application/json")
+        })
+}
+
+/// Middleware rendering `APIError` responses in the locale of the request, as
+/// plain text (the message only) unless the client accepts `application/json`,
+/// in which case the body is the `ErrorBody` JSON.
+/// CAVEAT: Must be wrapped inside the `SessionMiddleware`.
+pub async fn negotiate_error<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let res = next.call(req).await?;
+
+    let response = match res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<APIError>())
+    {
+        Some(e) => {
+            let locale = Locale::of_request(res.request());
+            let mut response = HttpResponse::build(res.status());
+            if let Some(retry_after) = res.headers().get(header::RETRY_AFTER) {
+                response.insert_header((header::RETRY_AFTER, retry_after.clone()));
+            }
+            if accepts_json(res.request()) {
+                response.json(e.body(locale))
+            } else {
+                response
+                    .insert_header(ContentType::html())
+                    .body(e.message(locale))
+            }
+        }
+        None => return Ok(res.map_into_left_body()),
+    };
+
+    Ok(res.into_response(response).map_into_right_body())
+}
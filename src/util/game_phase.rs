@@ -0,0 +1,124 @@
+use std::ops::DerefMut;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_util::{log_server_error, APIError, ERROR_DB_UNKNOWN};
+use super::config::config;
+
+/// The phases of the hunt, switched by an admin with `/staff_set_phase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum GamePhase {
+    /// Teams register, but no puzzle can be unlocked or submitted.
+    PreHunt,
+    Running,
+    /// Like `Running`, but `/puzzle_status` shows the counts as of the freeze.
+    Frozen,
+    /// Nothing can be unlocked or submitted any more.
+    Ended,
+    /// Answers are checked and reported without changing rankings or balances,
+    /// and unlocking is free.
+    PostHunt,
+}
+
+impl GamePhase {
+    /// The `phase` column of `game_phase`. Unknown values are `Running`.
+    ///
+    /// ```
+    /// use server::util::game_phase::GamePhase;
+    ///
+    /// for phase in [GamePhase::PreHunt, GamePhase::Frozen, GamePhase::PostHunt] {
+    ///     assert_eq!(GamePhase::from_db(phase.to_db()), phase);
+    /// }
+    /// ```
+    pub fn from_db(value: i32) -> Self {
+        match value {
+            0 => GamePhase::PreHunt,
+            2 => GamePhase::Frozen,
+            3 => GamePhase::Ended,
+            4 => GamePhase::PostHunt,
+            _ => GamePhase::Running,
+        }
+    }
+
+    pub fn to_db(self) -> i32 {
+        match self {
+            GamePhase::PreHunt => 0,
+            GamePhase::Running => 1,
+            GamePhase::Frozen => 2,
+            GamePhase::Ended => 3,
+            GamePhase::PostHunt => 4,
+        }
+    }
+
+    /// Whether unlocks, submissions and oracles change balances and rankings.
+    pub fn counted(self) -> bool {
+        matches!(self, GamePhase::Running | GamePhase::Frozen)
+    }
+
+    /// Puzzles can be seen from `Running` on.
+    pub fn check_started(self) -> Result<(), APIError> {
+        match self {
+            GamePhase::PreHunt => Err(APIError::NotAvailable),
+            _ => Ok(()),
+        }
+    }
+
+    /// Puzzles can be unlocked and answered, for the record or not.
+    pub fn check_playable(self) -> Result<(), APIError> {
+        match self {
+            GamePhase::Running | GamePhase::Frozen | GamePhase::PostHunt => Ok(()),
+            _ => Err(APIError::NotAvailable),
+        }
+    }
+
+    pub fn check_counted(self) -> Result<(), APIError> {
+        if self.counted() {
+            Ok(())
+        } else {
+            Err(APIError::NotAvailable)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseState {
+    pub phase: GamePhase,
+    pub since: DateTime<Utc>,
+}
+
+impl PhaseState {
+    /// Solves and unlocks after this are hidden from the public statistics.
+    pub fn frozen_at(&self) -> Option<DateTime<Utc>> {
+        (self.phase == GamePhase::Frozen).then_some(self.since)
+    }
+}
+
+/// The latest switch, or `Running` since the epoch if there was none.
+pub async fn fetch_phase<C>(conn: &mut C) -> Result<PhaseState, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::game_phase::dsl::*;
+
+    match game_phase
+        .order(id.desc())
+        .select((phase, changed_at))
+        .first::<(i32, DateTime<Utc>)>(conn)
+        .await
+    {
+        Ok((value, time)) => Ok(PhaseState {
+            phase: GamePhase::from_db(value),
+            since: time,
+        }),
+        Err(Error::NotFound) => Ok(PhaseState {
+            phase: GamePhase::Running,
+            since: config().game.epoch,
+        }),
+        Err(e) => Err(log_server_error(e, "game_phase", ERROR_DB_UNKNOWN)),
+    }
+}
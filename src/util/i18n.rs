@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+
+use actix_session::SessionExt;
+use actix_web::dev::Payload;
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::{Error, FromRequest, HttpRequest};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_util::SESSION_LOCALE;
+
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "en")]
+    En,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::ZhCn, Locale::En];
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::En => "en",
+        }
+    }
+
+    /// Matches a language tag by its primary language, e.g. `en-GB` is `En`.
+    pub fn parse(tag: &str) -> Option<Locale> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        if primary.eq_ignore_ascii_case("zh") {
+            Some(Locale::ZhCn)
+        } else if primary.eq_ignore_ascii_case("en") {
+            Some(Locale::En)
+        } else {
+            None
+        }
+    }
+
+    /// The supported locale with the highest quality in an `Accept-Language`.
+    ///
+    /// ``` rust
+    /// use server::util::i18n::Locale;
+    ///
+    /// assert_eq!(Locale::from_accept_language("en-US,en;q=0.9,zh-CN;q=0.8"), Some(Locale::En));
+    /// assert_eq!(Locale::from_accept_language("fr;q=1.0, zh;q=0.5, en;q=0.3"), Some(Locale::ZhCn));
+    /// assert_eq!(Locale::from_accept_language("en;q=0, zh-TW"), Some(Locale::ZhCn));
+    /// assert_eq!(Locale::from_accept_language("fr, de"), None);
+    /// ```
+    pub fn from_accept_language(header: &str) -> Option<Locale> {
+        header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let locale = Locale::parse(parts.next()?)?;
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (quality > 0.0).then_some((locale, quality))
+            })
+            // The first one wins a tie.
+            .fold(
+                None,
+                |best: Option<(Locale, f32)>, (locale, quality)| match best {
+                    Some((_, best_quality)) if best_quality >= quality => best,
+                    _ => Some((locale, quality)),
+                },
+            )
+            .map(|(locale, _)| locale)
+    }
+
+    /// The preference saved in the session, then `Accept-Language`.
+    pub fn of_request(req: &HttpRequest) -> Locale {
+        req.get_session()
+            .get::<String>(SESSION_LOCALE)
+            .ok()
+            .flatten()
+            .and_then(|tag| Locale::parse(&tag))
+            .or_else(|| {
+                req.headers()
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(Locale::from_accept_language)
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl FromRequest for Locale {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Locale::of_request(req)))
+    }
+}
+
+fn load_catalogue(source: &str) -> HashMap<String, String> {
+    let table: toml::Table = toml::from_str(source).expect("Invalid message catalogue");
+    table
+        .into_iter()
+        .flat_map(|(group, messages)| match messages {
+            toml::Value::Table(messages) => messages
+                .into_iter()
+                .map(|(code, message)| {
+                    (
+                        format!("{group}.{code}"),
+                        message
+                            .as_str()
+                            .expect("Messages must be strings")
+                            .to_string(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            _ => panic!("Message catalogue entries must be grouped"),
+        })
+        .collect()
+}
+
+static CATALOGUE: Lazy<HashMap<Locale, HashMap<String, String>>> = Lazy::new(|| {
+    HashMap::from([
+        (
+            Locale::ZhCn,
+            load_catalogue(include_str!("../../locales/zh-CN.toml")),
+        ),
+        (
+            Locale::En,
+            load_catalogue(include_str!("../../locales/en.toml")),
+        ),
+    ])
+});
+
+/// Keys missing in any of the locales.
+///
+/// ``` rust
+/// assert_eq!(server::util::i18n::missing_keys(), Vec::<String>::new());
+/// ```
+pub fn missing_keys() -> Vec<String> {
+    let mut missing: Vec<String> = CATALOGUE
+        .values()
+        .flat_map(|messages| messages.keys())
+        .filter(|key| {
+            Locale::ALL
+                .iter()
+                .any(|locale| !CATALOGUE[locale].contains_key(*key))
+        })
+        .cloned()
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+/// Looks up a message, falling back to the default locale and then the key.
+pub fn t(locale: Locale, key: &str) -> String {
+    t_args(locale, key, &[])
+}
+
+/// Looks up a message and fills in its `{name}` parameters.
+pub fn t_args(locale: Locale, key: &str, args: &[(&str, String)]) -> String {
+    let template = CATALOGUE[&locale]
+        .get(key)
+        .or_else(|| CATALOGUE[&Locale::default()].get(key))
+        .map_or(key, String::as_str);
+
+    args.iter()
+        .fold(template.to_string(), |message, (name, value)| {
+            message.replace(&format!("{{{name}}}"), value)
+        })
+}
@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_web::web;
+use chrono::Utc;
+use derive_more::derive::Display;
+use lettre::address::AddressError;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::info;
+
+use super::api_util::{log_server_error, APIError};
+use super::config::{MailConfig, MailTransport};
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Display)]
+pub enum MailError {
+    #[display("mail io error: {_0}")]
+    Io(std::io::Error),
+    #[display("mail transport error: {_0}")]
+    Transport(String),
+}
+
+/// Delivers mails. Implementations may block, they are always called on a
+/// blocking thread through `send_mail`.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+/// Writes each mail as a file into a spool directory, for local testing.
+pub struct SpoolMailer {
+    dir: PathBuf,
+}
+
+impl SpoolMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Mailer for SpoolMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        fs::create_dir_all(&self.dir).map_err(MailError::Io)?;
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            uuid::Uuid::new_v4()
+        ));
+        fs::write(
+            &path,
+            format!(
+                "To: {}\r\nSubject: {}\r\nDate: {}\r\n\r\n{}\r\n",
+                mail.to,
+                mail.subject,
+                Utc::now().to_rfc2822(),
+                mail.body
+            ),
+        )
+        .map_err(MailError::Io)?;
+        info!("Spooled mail to {} at {}", mail.to, path.display());
+        Ok(())
+    }
+}
+
+/// Delivers mails through an SMTP relay.
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Uses implicit TLS (usually port 465), or STARTTLS (usually port 587)
+    /// if `starttls` is set.
+    pub fn new(
+        host: &str,
+        port: u16,
+        starttls: bool,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<Self, MailError> {
+        let builder = if starttls {
+            SmtpTransport::starttls_relay(host)
+        } else {
+            SmtpTransport::relay(host)
+        }
+        .map_err(|e| MailError::Transport(e.to_string()))?
+        .port(port);
+
+        let builder = match credentials {
+            Some((username, password)) => builder.credentials(Credentials::new(username, password)),
+            None => builder,
+        };
+
+        Ok(Self {
+            transport: builder.build(),
+            from: from
+                .parse()
+                .map_err(|e: AddressError| MailError::Transport(e.to_string()))?,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail
+                .to
+                .parse()
+                .map_err(|e: AddressError| MailError::Transport(e.to_string()))?)
+            .subject(mail.subject.as_str())
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(|e| MailError::Transport(e.to_string()))?;
+
+        self.transport
+            .send(&message)
+            .map_err(|e| MailError::Transport(e.to_string()))?;
+        info!("Sent mail to {}", mail.to);
+        Ok(())
+    }
+}
+
+pub type DynMailer = Arc<dyn Mailer>;
+
+/// The mailer of the `transport` configured, see `MailConfig`.
+pub fn mailer_from_config(config: &MailConfig) -> Result<DynMailer, MailError> {
+    Ok(match config.transport {
+        MailTransport::Smtp => Arc::new(SmtpMailer::new(
+            &config.smtp_host,
+            config.smtp_port,
+            config.smtp_starttls,
+            config
+                .smtp_username
+                .clone()
+                .map(|username| (username, config.smtp_password.clone())),
+            &config.from,
+        )?),
+        MailTransport::Spool => Arc::new(SpoolMailer::new(&config.spool_dir)),
+    })
+}
+
+pub async fn send_mail(
+    mailer: &DynMailer,
+    mail: Mail,
+    location: &'static str,
+) -> Result<(), APIError> {
+    let mailer = mailer.clone();
+    web::block(move || mailer.send(&mail))
+        .await
+        .map_err(|e| log_server_error(e, location, ERROR_MAIL))?
+        .map_err(|e| log_server_error(e, location, ERROR_MAIL))
+}
+
+pub static ERROR_MAIL: &str = "mail_delivery_failed";
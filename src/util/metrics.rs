@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::Error;
+use once_cell::sync::Lazy;
+
+use super::api_version::unversioned;
+use super::config::config;
+use crate::DbPool;
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+#[derive(Debug, Default)]
+struct RouteMetrics {
+    statuses: Mutex<HashMap<u16, u64>>,
+    latency: [AtomicU64; LATENCY_BUCKETS.len()], // not cumulative, the slower ones are left out
+    latency_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+pub struct CacheCounters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    /// Removed for the size or the expiration, not invalidated or replaced.
+    pub evictions: AtomicU64,
+}
+
+/// The counters of this instance, exported by `/metrics` in the Prometheus
+/// text format. Every instance counts on its own, so sum them up.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    routes: Mutex<HashMap<(String, String), Arc<RouteMetrics>>>, // by (method, route)
+    errors: Mutex<HashMap<&'static str, u64>>,                   // by `APIError::code`
+    caches: Mutex<HashMap<&'static str, Arc<CacheCounters>>>,
+    tokens_minted: AtomicU64,
+    tokens_burned: AtomicU64,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+// Label values are route patterns and codes, but quote them anyway.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    fn route(&self, method: String, route: String) -> Arc<RouteMetrics> {
+        self.routes
+            .lock()
+            .expect("Route metrics poisoned")
+            .entry((method, route))
+            .or_default()
+            .clone()
+    }
+
+    fn observe(&self, method: String, route: String, status: u16, elapsed: Duration) {
+        let metrics = self.route(method, route);
+        *metrics
+            .statuses
+            .lock()
+            .expect("Route metrics poisoned")
+            .entry(status)
+            .or_default() += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            metrics.latency[i].fetch_add(1, Ordering::Relaxed);
+        }
+        metrics
+            .latency_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        metrics.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_error(&self, code: &'static str) {
+        *self
+            .errors
+            .lock()
+            .expect("Error metrics poisoned")
+            .entry(code)
+            .or_default() += 1;
+    }
+
+    /// The counters of the cache `name`, shared by the caches of that name.
+    pub fn cache(&self, name: &'static str) -> Arc<CacheCounters> {
+        self.caches
+            .lock()
+            .expect("Cache metrics poisoned")
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// CAVEAT: Called once the sql transaction updating the balance is
+    /// committed, a rolled back one changed nothing.
+    pub fn count_tokens(&self, amount: i64) {
+        if amount > 0 {
+            self.tokens_minted
+                .fetch_add(amount as u64, Ordering::Relaxed);
+        } else {
+            self.tokens_burned
+                .fetch_add(amount.unsigned_abs(), Ordering::Relaxed);
+        }
+    }
+
+    /// Everything but the DB pool, see `render_pool`.
+    ///
+    /// ```
+    /// use server::util::metrics::Metrics;
+    ///
+    /// let metrics = Metrics::default();
+    /// metrics.count_tokens(100);
+    /// metrics.count_tokens(-30);
+    /// metrics.count_error("not_found");
+    /// let text = metrics.render();
+    /// assert!(text.contains("tokens_minted_total 100\n"));
+    /// assert!(text.contains("tokens_burned_total 30\n"));
+    /// assert!(text.contains("api_errors_total{code=\"not_found\"} 1\n"));
+    /// ```
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut routes: Vec<((String, String), Arc<RouteMetrics>)> = self
+            .routes
+            .lock()
+            .expect("Route metrics poisoned")
+            .iter()
+            .map(|(key, metrics)| (key.clone(), metrics.clone()))
+            .collect();
+        routes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        out.push_str("# HELP http_requests_total Requests by method, route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route), metrics) in &routes {
+            let mut statuses: Vec<(u16, u64)> = metrics
+                .statuses
+                .lock()
+                .expect("Route metrics poisoned")
+                .iter()
+                .map(|(status, count)| (*status, *count))
+                .collect();
+            statuses.sort();
+            for (status, count) in statuses {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{status}\"}} {count}",
+                    escape(method),
+                    escape(route),
+                );
+            }
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Latency by method and route.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), metrics) in &routes {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            let mut cumulative = 0;
+            for (le, bucket) in LATENCY_BUCKETS.iter().zip(&metrics.latency) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {cumulative}"
+                );
+            }
+            let count = metrics.count.load(Ordering::Relaxed);
+            let seconds = metrics.latency_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {seconds}"
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {count}"
+            );
+        }
+
+        let mut errors: Vec<(&'static str, u64)> = self
+            .errors
+            .lock()
+            .expect("Error metrics poisoned")
+            .iter()
+            .map(|(code, count)| (*code, *count))
+            .collect();
+        errors.sort();
+        out.push_str("# HELP api_errors_total Failed requests by error code.\n");
+        out.push_str("# TYPE api_errors_total counter\n");
+        for (code, count) in errors {
+            let _ = writeln!(out, "api_errors_total{{code=\"{}\"}} {count}", escape(code));
+        }
+
+        let mut caches: Vec<(&'static str, Arc<CacheCounters>)> = self
+            .caches
+            .lock()
+            .expect("Cache metrics poisoned")
+            .iter()
+            .map(|(name, counters)| (*name, counters.clone()))
+            .collect();
+        caches.sort_by_key(|(name, _)| *name);
+        for (metric, help, counter) in [
+            (
+                "cache_hits_total",
+                "Lookups answered by the cache.",
+                (|c: &CacheCounters| &c.hits) as fn(&CacheCounters) -> &AtomicU64,
+            ),
+            (
+                "cache_misses_total",
+                "Lookups fetched from the database.",
+                |c| &c.misses,
+            ),
+            (
+                "cache_evictions_total",
+                "Entries removed for the size or the expiration.",
+                |c| &c.evictions,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {metric} {help}");
+            let _ = writeln!(out, "# TYPE {metric} counter");
+            for (name, counters) in &caches {
+                let _ = writeln!(
+                    out,
+                    "{metric}{{cache=\"{}\"}} {}",
+                    escape(name),
+                    counter(counters).load(Ordering::Relaxed)
+                );
+            }
+        }
+
+        out.push_str("# HELP tokens_minted_total Tokens given to teams.\n");
+        out.push_str("# TYPE tokens_minted_total counter\n");
+        let _ = writeln!(
+            out,
+            "tokens_minted_total {}",
+            self.tokens_minted.load(Ordering::Relaxed)
+        );
+        out.push_str("# HELP tokens_burned_total Tokens spent or fined.\n");
+        out.push_str("# TYPE tokens_burned_total counter\n");
+        let _ = writeln!(
+            out,
+            "tokens_burned_total {}",
+            self.tokens_burned.load(Ordering::Relaxed)
+        );
+
+        out
+    }
+}
+
+/// The usage of the DB pool, appended to `out`.
+pub fn render_pool(pool: &DbPool, out: &mut String) {
+    let state = pool.state();
+    let stats = &state.statistics;
+
+    out.push_str("# HELP db_pool_connections Connections of the pool by state.\n");
+    out.push_str("# TYPE db_pool_connections gauge\n");
+    let _ = writeln!(
+        out,
+        "db_pool_connections{{state=\"idle\"}} {}",
+        state.idle_connections
+    );
+    let _ = writeln!(
+        out,
+        "db_pool_connections{{state=\"in_use\"}} {}",
+        state.connections - state.idle_connections
+    );
+    out.push_str("# HELP db_pool_max_connections The size of the pool.\n");
+    out.push_str("# TYPE db_pool_max_connections gauge\n");
+    let _ = writeln!(
+        out,
+        "db_pool_max_connections {}",
+        config().database.pool_size
+    );
+
+    out.push_str("# HELP db_pool_gets_total Connections taken from the pool, by whether they were waited for.\n");
+    out.push_str("# TYPE db_pool_gets_total counter\n");
+    for (result, count) in [
+        ("direct", stats.get_direct),
+        ("waited", stats.get_waited),
+        ("timed_out", stats.get_timed_out),
+    ] {
+        let _ = writeln!(out, "db_pool_gets_total{{result=\"{result}\"}} {count}");
+    }
+    out.push_str("# HELP db_pool_get_wait_seconds_total Time spent waiting for a connection.\n");
+    out.push_str("# TYPE db_pool_get_wait_seconds_total counter\n");
+    let _ = writeln!(
+        out,
+        "db_pool_get_wait_seconds_total {}",
+        stats.get_wait_time.as_secs_f64()
+    );
+
+    out.push_str("# HELP db_pool_connections_created_total Connections opened.\n");
+    out.push_str("# TYPE db_pool_connections_created_total counter\n");
+    let _ = writeln!(
+        out,
+        "db_pool_connections_created_total {}",
+        stats.connections_created
+    );
+    out.push_str("# HELP db_pool_connections_closed_total Connections closed by reason.\n");
+    out.push_str("# TYPE db_pool_connections_closed_total counter\n");
+    for (reason, count) in [
+        ("broken", stats.connections_closed_broken),
+        ("invalid", stats.connections_closed_invalid),
+        ("max_lifetime", stats.connections_closed_max_lifetime),
+        ("idle_timeout", stats.connections_closed_idle_timeout),
+    ] {
+        let _ = writeln!(
+            out,
+            "db_pool_connections_closed_total{{reason=\"{reason}\"}} {count}"
+        );
+    }
+}
+
+/// Middleware counting the requests and their latency by route. Rejected
+/// requests are counted too when it wraps `rate_limit`.
+pub async fn record_metrics<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error>
+where
+    B: MessageBody + 'static,
+{
+    // Unmatched paths share one route, so scanners cannot grow the counters.
+    let route = req.match_pattern().map_or_else(
+        || "[unmatched]".to_string(),
+        |pattern| unversioned(&pattern).to_string(),
+    );
+    // Methods are not a closed set, so the unusual ones share a label.
+    let method = match *req.method() {
+        Method::GET
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::PATCH
+        | Method::HEAD
+        | Method::OPTIONS => req.method().to_string(),
+        _ => "OTHER".to_string(),
+    };
+    let start = Instant::now();
+
+    let res = next.call(req).await?;
+    metrics().observe(method, route, res.status().as_u16(), start.elapsed());
+    Ok(res)
+}
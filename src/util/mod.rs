@@ -1,7 +1,21 @@
 #[macro_use]
 pub mod api_util;
+pub mod api_version;
+pub mod attempt_limiter;
 pub mod auto_fetch;
 pub mod cache;
 pub mod cipher_util;
+pub mod config;
 pub mod economy;
+pub mod error_negotiation;
+pub mod game_phase;
+pub mod i18n;
+pub mod mailer;
+pub mod metrics;
+pub mod notify;
+pub mod penalty;
+pub mod puzzle_graph;
+pub mod rate_limit;
+pub mod schedule;
+pub mod session_guard;
 pub mod stat;
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use log::{error, info};
+
+use super::mailer::{DynMailer, Mail};
+use crate::models::TeamId;
+use crate::DbPool;
+
+/// Mails notifications to verified addresses in the background.
+pub struct Notifier {
+    mailer: DynMailer,
+    pool: Arc<DbPool>,
+}
+
+impl Notifier {
+    pub fn new(mailer: DynMailer, pool: Arc<DbPool>) -> Self {
+        Self { mailer, pool }
+    }
+
+    /// Notifies every member of the team.
+    pub fn notify_team(&self, team_id: TeamId, subject: String, body: String) {
+        self.deliver(Some(team_id), subject, body)
+    }
+
+    /// Notifies every user, e.g. for announcements.
+    pub fn notify_everyone(&self, subject: String, body: String) {
+        self.deliver(None, subject, body)
+    }
+
+    // Fire and forget, failures are only logged.
+    fn deliver(&self, team_id: Option<TeamId>, subject: String, body: String) {
+        use crate::schema::email::dsl as email_dsl;
+        use crate::schema::users::dsl as users_dsl;
+
+        let mailer = self.mailer.clone();
+        let pool = self.pool.clone();
+
+        tokio::spawn(async move {
+            let mut conn = match pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => return error!("Notification failed to connect db: {e}"),
+            };
+
+            let mut query = email_dsl::email
+                .inner_join(users_dsl::users)
+                .filter(email_dsl::verified_at.is_not_null())
+                .select(email_dsl::email_record)
+                .into_boxed();
+            if let Some(team_id) = team_id {
+                query = query.filter(users_dsl::team.eq(team_id));
+            }
+
+            let recipients = match query.load::<String>(&mut conn).await {
+                Ok(recipients) => recipients,
+                Err(e) => return error!("Notification failed to load recipients: {e}"),
+            };
+            drop(conn);
+
+            info!(
+                "Notifying {} recipients of team {team_id:?}: {subject}",
+                recipients.len()
+            );
+
+            for to in recipients {
+                let mailer = mailer.clone();
+                let mail = Mail {
+                    to,
+                    subject: subject.clone(),
+                    body: body.clone(),
+                };
+                match tokio::task::spawn_blocking(move || mailer.send(&mail)).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => error!("Notification failed: {e}"),
+                    Err(e) => error!("Notification panicked: {e}"),
+                }
+            }
+        });
+    }
+}
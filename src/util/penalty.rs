@@ -0,0 +1,184 @@
+use std::ops::DerefMut;
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::models::{PuzzleId, WaPenalty};
+
+use super::api_util::{new_unlocated_server_error, APIError, ERROR_DB_UNKNOWN};
+
+static TIME_PENALTY: [i64; 11] = [21, 29, 41, 57, 80, 112, 156, 219, 306, 429, 600]; // in seconds
+static TOKEN_PENALTY: [i64; 11] = [129, 155, 186, 223, 268, 322, 386, 463, 556, 667, 800];
+
+/// How wrong answers to a puzzle are punished: the `penalty_schedule` of the
+/// puzzle, else of its round, else the default one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PenaltySchedule {
+    /// Seconds to wait by level, the last one for the levels beyond.
+    pub time_penalty: Vec<i64>,
+    /// Tokens fined by level, the last one for the levels beyond.
+    pub token_penalty: Vec<i64>,
+    /// Wrong answers costing nothing before the penalties start.
+    pub free_attempts: i32,
+    /// Both levels drop by one every so long without a wrong answer.
+    pub decay: Option<TimeDelta>,
+    /// A new mid answer resets the time level as well as the token level.
+    pub reset_time_on_mid_answer: bool,
+}
+
+impl Default for PenaltySchedule {
+    fn default() -> Self {
+        Self {
+            time_penalty: TIME_PENALTY.to_vec(),
+            token_penalty: TOKEN_PENALTY.to_vec(),
+            free_attempts: 0,
+            decay: None,
+            reset_time_on_mid_answer: false,
+        }
+    }
+}
+
+// The table checks the penalties are not negative, a negative fine would
+// mint tokens though.
+fn at_level(schedule: &[i64], level: i32) -> i64 {
+    schedule
+        .get(level.max(0) as usize)
+        .or(schedule.last())
+        .cloned()
+        .unwrap_or(0)
+        .max(0)
+}
+
+impl Default for WaPenalty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaPenalty {
+    pub fn new() -> Self {
+        Self {
+            time_penalty_until: Utc.timestamp_opt(1, 0).unwrap(),
+            token_penalty_level: 0,
+            time_penalty_level: 0,
+            wrong_attempts: 0,
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Forgives a level per `decay` passed since the last wrong answer.
+    ///
+    /// ```
+    /// use chrono::{TimeDelta, Utc};
+    /// use server::models::WaPenalty;
+    /// use server::util::penalty::PenaltySchedule;
+    ///
+    /// let schedule = PenaltySchedule {
+    ///     decay: Some(TimeDelta::minutes(10)),
+    ///     ..Default::default()
+    /// };
+    /// let now = Utc::now();
+    /// let mut penalty = WaPenalty {
+    ///     token_penalty_level: 3,
+    ///     time_penalty_level: 1,
+    ///     updated_at: now - TimeDelta::minutes(25),
+    ///     ..WaPenalty::new()
+    /// };
+    /// penalty.decay(&schedule, now);
+    /// assert_eq!((penalty.token_penalty_level, penalty.time_penalty_level), (1, 0));
+    /// ```
+    pub fn decay(&mut self, schedule: &PenaltySchedule, now: DateTime<Utc>) {
+        let Some(decay) = schedule.decay.filter(|d| *d > TimeDelta::zero()) else {
+            return;
+        };
+        let steps = ((now - self.updated_at).num_seconds() / decay.num_seconds().max(1))
+            .clamp(0, i32::MAX as i64) as i32;
+        self.token_penalty_level = (self.token_penalty_level - steps).max(0);
+        self.time_penalty_level = (self.time_penalty_level - steps).max(0);
+    }
+
+    /// Returns the fine, zero for a free attempt.
+    pub fn on_wrong_answer(&mut self, schedule: &PenaltySchedule) -> i64 {
+        let now = Utc::now();
+        self.decay(schedule, now);
+        self.wrong_attempts += 1;
+        self.updated_at = now;
+        if self.wrong_attempts <= schedule.free_attempts {
+            return 0;
+        }
+
+        let time_penalty = at_level(&schedule.time_penalty, self.time_penalty_level);
+        let token_penalty = at_level(&schedule.token_penalty, self.token_penalty_level);
+        self.token_penalty_level += 1;
+        self.time_penalty_level += 1;
+        self.time_penalty_until = now + TimeDelta::seconds(time_penalty);
+        token_penalty
+    }
+
+    pub fn on_new_mid_answer(self, schedule: &PenaltySchedule) -> Self {
+        Self {
+            token_penalty_level: 0,
+            time_penalty_level: if schedule.reset_time_on_mid_answer {
+                0
+            } else {
+                self.time_penalty_level
+            },
+            ..self
+        }
+    }
+}
+
+pub async fn fetch_penalty_schedule<C>(
+    puzzle_id: PuzzleId,
+    conn: &mut C,
+) -> Result<PenaltySchedule, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::penalty_schedule::dsl as schedule_dsl;
+    use crate::schema::puzzle::dsl as puzzle_dsl;
+    use crate::schema::puzzle_round::dsl as round_dsl;
+
+    let schedule_id = match puzzle_dsl::puzzle
+        .left_join(round_dsl::puzzle_round)
+        .filter(puzzle_dsl::id.eq(puzzle_id))
+        .select((
+            puzzle_dsl::penalty_schedule,
+            round_dsl::penalty_schedule.nullable(),
+        ))
+        .first::<(Option<i32>, Option<i32>)>(conn)
+        .await
+    {
+        Ok((own, round)) => own.or(round),
+        Err(Error::NotFound) => return Err(APIError::NotFound),
+        Err(e) => return Err(new_unlocated_server_error(e, ERROR_DB_UNKNOWN)),
+    };
+
+    let Some(schedule_id) = schedule_id else {
+        return Ok(PenaltySchedule::default());
+    };
+
+    let (time_penalty, token_penalty, free_attempts, decay_minutes, reset_time_on_mid_answer) =
+        schedule_dsl::penalty_schedule
+            .filter(schedule_dsl::id.eq(schedule_id))
+            .select((
+                schedule_dsl::time_penalty,
+                schedule_dsl::token_penalty,
+                schedule_dsl::free_attempts,
+                schedule_dsl::decay_minutes,
+                schedule_dsl::reset_time_on_mid_answer,
+            ))
+            .first::<(Vec<i64>, Vec<i64>, i32, Option<i32>, bool)>(conn)
+            .await
+            .map_err(|e| new_unlocated_server_error(e, ERROR_DB_UNKNOWN))?;
+
+    Ok(PenaltySchedule {
+        time_penalty,
+        token_penalty,
+        free_attempts,
+        decay: decay_minutes.map(|m| TimeDelta::minutes(m as i64)),
+        reset_time_on_mid_answer,
+    })
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::DerefMut;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::models::{DecipherId, PuzzleId, TeamId};
+
+use super::api_util::{log_server_error, APIError, ERROR_DB_UNKNOWN};
+use super::auto_fetch::Expiration;
+use super::cache::Cache;
+use super::schedule::Availability;
+
+/// How a team gets a puzzle, the `unlock_rule` and `unlock_count` of `puzzle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockRule {
+    /// Pay `deciper_price` for its decipher.
+    Purchase,
+    /// Free as soon as its round opens.
+    RoundOpen,
+    /// Free after solving this many puzzles of its round.
+    SolvedInRound(usize),
+    /// Free after solving all of its prerequisites, e.g. the feeders of a meta.
+    Prerequisites,
+}
+
+impl UnlockRule {
+    /// Unknown rules fall back to `Purchase`.
+    ///
+    /// ```
+    /// use server::util::puzzle_graph::UnlockRule;
+    ///
+    /// assert_eq!(UnlockRule::from_db(2, 3), UnlockRule::SolvedInRound(3));
+    /// assert_eq!(UnlockRule::from_db(7, 0), UnlockRule::Purchase);
+    /// ```
+    pub fn from_db(rule: i32, count: i32) -> Self {
+        match rule {
+            1 => UnlockRule::RoundOpen,
+            2 => UnlockRule::SolvedInRound(count.max(0) as usize),
+            3 => UnlockRule::Prerequisites,
+            _ => UnlockRule::Purchase,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Round {
+    pub id: i32,
+    pub title: String,
+    pub opens_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    pub puzzle_id: PuzzleId,
+    pub title: String,
+    pub decipher: DecipherId,
+    pub round: Option<i32>,
+    pub rule: UnlockRule,
+    pub requires: Vec<PuzzleId>,
+}
+
+/// Every puzzle with its round and unlock rule. Puzzles without a round are
+/// always open.
+#[derive(Debug, Clone, Default)]
+pub struct PuzzleGraph {
+    pub rounds: HashMap<i32, Round>,
+    pub puzzles: Vec<GraphNode>,
+}
+
+impl PuzzleGraph {
+    pub fn round_open(&self, round: Option<i32>, now: DateTime<Utc>) -> bool {
+        match round {
+            None => true,
+            Some(id) => self.rounds.get(&id).is_some_and(|r| r.opens_at <= now),
+        }
+    }
+
+    /// Puzzles of open rounds that a team having solved `solved` gets for free.
+    /// Already unlocked ones are included as well.
+    pub fn free_unlocks<'a>(
+        &'a self,
+        solved: &'a HashSet<PuzzleId>,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = &'a GraphNode> + 'a {
+        let mut solved_in_round: HashMap<Option<i32>, usize> = HashMap::new();
+        for node in self
+            .puzzles
+            .iter()
+            .filter(|p| solved.contains(&p.puzzle_id))
+        {
+            *solved_in_round.entry(node.round).or_default() += 1;
+        }
+
+        self.puzzles
+            .iter()
+            .filter(move |node| self.round_open(node.round, now))
+            .filter(move |node| match node.rule {
+                UnlockRule::Purchase => false,
+                UnlockRule::RoundOpen => true,
+                UnlockRule::SolvedInRound(count) => {
+                    solved_in_round.get(&node.round).copied().unwrap_or(0) >= count
+                }
+                UnlockRule::Prerequisites => node.requires.iter().all(|p| solved.contains(p)),
+            })
+    }
+
+    /// Whether a team may still pay for a decipher. Deciphers used by no puzzle
+    /// are, as before rounds existed.
+    pub fn purchasable(&self, decipher: DecipherId, now: DateTime<Utc>) -> bool {
+        let mut nodes = self
+            .puzzles
+            .iter()
+            .filter(|p| p.decipher == decipher)
+            .peekable();
+        nodes.peek().is_none()
+            || nodes.any(|p| p.rule == UnlockRule::Purchase && self.round_open(p.round, now))
+    }
+}
+
+pub async fn fetch_graph<C>(conn: &mut C) -> Result<PuzzleGraph, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::puzzle::dsl as puzzle_dsl;
+    use crate::schema::puzzle_prerequisite::dsl as prerequisite_dsl;
+    use crate::schema::puzzle_round::dsl as round_dsl;
+
+    let rounds = round_dsl::puzzle_round
+        .select((round_dsl::id, round_dsl::title, round_dsl::opens_at))
+        .load::<(i32, String, DateTime<Utc>)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "puzzle_graph", ERROR_DB_UNKNOWN))?;
+
+    let mut requires: HashMap<PuzzleId, Vec<PuzzleId>> = HashMap::new();
+    for (puzzle_id, required) in prerequisite_dsl::puzzle_prerequisite
+        .select((prerequisite_dsl::puzzle, prerequisite_dsl::requires))
+        .load::<(i32, i32)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "puzzle_graph", ERROR_DB_UNKNOWN))?
+    {
+        requires.entry(puzzle_id).or_default().push(required);
+    }
+
+    let puzzles = puzzle_dsl::puzzle
+        .select((
+            puzzle_dsl::id,
+            puzzle_dsl::title,
+            puzzle_dsl::decipher,
+            puzzle_dsl::round,
+            puzzle_dsl::unlock_rule,
+            puzzle_dsl::unlock_count,
+        ))
+        .order(puzzle_dsl::id)
+        .load::<(i32, String, i32, Option<i32>, i32, i32)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "puzzle_graph", ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .map(
+            |(puzzle_id, title, decipher, round, rule, count)| GraphNode {
+                puzzle_id,
+                title,
+                decipher,
+                round,
+                rule: UnlockRule::from_db(rule, count),
+                requires: requires.remove(&puzzle_id).unwrap_or_default(),
+            },
+        )
+        .collect();
+
+    Ok(PuzzleGraph {
+        rounds: rounds
+            .into_iter()
+            .map(|(id, title, opens_at)| {
+                (
+                    id,
+                    Round {
+                        id,
+                        title,
+                        opens_at,
+                    },
+                )
+            })
+            .collect(),
+        puzzles,
+    })
+}
+
+/// Puzzles the team has fully solved.
+pub async fn fetch_solved<C>(conn: &mut C, team_id: TeamId) -> Result<HashSet<PuzzleId>, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::submission::dsl::*;
+
+    let solved = submission
+        .filter(team.eq(team_id))
+        .filter(depth.eq(0))
+        .select(puzzle)
+        .load::<i32>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "puzzle_graph", ERROR_DB_UNKNOWN))?;
+    Ok(solved.into_iter().collect())
+}
+
+/// Writes the free unlocks the team is entitled to through `unlock_cache`,
+/// returning the newly unlocked deciphers. Deciphers outside their release
+/// window are left for a later call.
+pub async fn grant_free_unlocks(
+    cache: &Cache,
+    graph: &PuzzleGraph,
+    team_id: TeamId,
+    solved: &HashSet<PuzzleId>,
+) -> Result<Vec<DecipherId>, APIError> {
+    let now = Utc::now();
+    let deciphers: HashSet<DecipherId> = graph
+        .free_unlocks(solved, now)
+        .map(|node| node.decipher)
+        .collect();
+
+    let mut granted = vec![];
+    for decipher_id in deciphers {
+        if cache
+            .unlock_cache
+            .get((team_id, decipher_id))
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        let decipher = cache.decipher_cache.get(decipher_id).await?;
+        if decipher.availability(now) != Availability::Open {
+            continue;
+        }
+        let level = (decipher.depth - 1).max(0);
+        cache
+            .unlock_cache
+            .set(
+                (team_id, decipher_id),
+                Some(level),
+                if level == 0 {
+                    Expiration::Long
+                } else {
+                    Expiration::Short
+                },
+            )
+            .await?;
+        granted.push(decipher_id);
+    }
+    Ok(granted)
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_session::SessionExt;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use log::{info, warn};
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_util::{APIError, SESSION_USER_ID};
+use super::api_version::unversioned;
+use crate::util::api_util::client_ip;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Budget {
+    /// Max burst of requests.
+    pub capacity: f64,
+    /// Tokens regained per second.
+    pub refill_per_second: f64,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            capacity: 60.0,
+            refill_per_second: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Budget of every route not listed in `routes`.
+    pub default: Budget,
+    /// An IP may host several sessions (e.g. a campus NAT), so its bucket is
+    /// the session budget times this factor.
+    pub ip_factor: f64,
+    /// Keyed by the route pattern, e.g. "/submit_answer".
+    pub routes: HashMap<String, Budget>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            default: Budget::default(),
+            ip_factor: 4.0,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+// A slower refill would wait longer than an hour for a token.
+const MIN_REFILL_PER_SECOND: f64 = 1.0 / 3600.0;
+
+impl Budget {
+    fn validate(&self, name: &str, errors: &mut Vec<String>) {
+        if !(self.capacity.is_finite() && self.capacity >= 1.0) {
+            errors.push(format!("{name}.capacity must be at least 1"));
+        }
+        if !(self.refill_per_second.is_finite() && self.refill_per_second >= MIN_REFILL_PER_SECOND)
+        {
+            errors.push(format!(
+                "{name}.refill_per_second must be at least {MIN_REFILL_PER_SECOND}"
+            ));
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Falls back to the default config if the file does not exist.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let config: Self = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| format!("{path}: {e}"))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Rate limit config {path} not found, using defaults.");
+                Self::default()
+            }
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+
+        let errors = config.validate();
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!("{path}:\n  {}", errors.join("\n  ")))
+        }
+    }
+
+    /// Every budget must let a request through and refill in finite time.
+    ///
+    /// ```
+    /// use server::util::rate_limit::{Budget, RateLimitConfig};
+    ///
+    /// let mut config = RateLimitConfig::default();
+    /// assert!(config.validate().is_empty());
+    /// config.routes.insert(
+    ///     "/login".to_string(),
+    ///     Budget { capacity: 0.5, refill_per_second: f64::NAN },
+    /// );
+    /// assert_eq!(config.validate().len(), 2);
+    /// ```
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        self.default.validate("default", &mut errors);
+        for (route, budget) in &self.routes {
+            budget.validate(&format!("routes.\"{route}\""), &mut errors);
+        }
+        if !(self.ip_factor.is_finite() && self.ip_factor >= 1.0) {
+            errors.push("ip_factor must be at least 1".to_string());
+        }
+        errors
+    }
+
+    fn budget(&self, route: &str) -> Budget {
+        self.routes.get(route).cloned().unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(budget: Budget) -> Self {
+        Self {
+            tokens: budget.capacity,
+            last: Instant::now(),
+        }
+    }
+
+    // Returns the time to wait if no token is left.
+    fn take(&mut self, budget: Budget) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * budget.refill_per_second).min(budget.capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if budget.refill_per_second > 0.0 {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / budget.refill_per_second,
+            ))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize)]
+enum BucketOwner {
+    Session(i32),
+    Ip(String),
+}
+
+#[derive(Debug, Default)]
+struct RouteCounter {
+    allowed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RouteCounterResponse {
+    route: String,
+    allowed: u64,
+    rejected: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RateLimitStatusResponse {
+    enabled: bool,
+    buckets: u64,
+    routes: Vec<RouteCounterResponse>,
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Cache<(BucketOwner, String), Arc<Mutex<TokenBucket>>>,
+    counters: Mutex<HashMap<String, Arc<RouteCounter>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        info!("Rate limit config: {config:?}");
+        Self {
+            config,
+            buckets: Cache::builder()
+                .max_capacity(65536)
+                // An idle bucket is full anyway.
+                .time_to_idle(Duration::from_secs(600))
+                .build(),
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn counter(&self, route: &str) -> Arc<RouteCounter> {
+        self.counters
+            .lock()
+            .expect("Rate limit counters poisoned")
+            .entry(route.to_string())
+            .or_default()
+            .clone()
+    }
+
+    async fn take(&self, owner: BucketOwner, route: &str, budget: Budget) -> Result<(), Duration> {
+        let bucket = self
+            .buckets
+            .get_with((owner, route.to_string()), async {
+                Arc::new(Mutex::new(TokenBucket::new(budget)))
+            })
+            .await;
+        let result = bucket
+            .lock()
+            .expect("Rate limit bucket poisoned")
+            .take(budget);
+        result
+    }
+
+    /// Returns the scope that ran out of tokens and the time to wait.
+    async fn check(
+        &self,
+        user_id: Option<i32>,
+        ip: String,
+        route: &str,
+    ) -> Result<(), (&'static str, Duration)> {
+        let budget = self.config.budget(route);
+
+        if let Some(user_id) = user_id {
+            self.take(BucketOwner::Session(user_id), route, budget)
+                .await
+                .map_err(|wait| ("session", wait))?;
+        }
+
+        let ip_budget = Budget {
+            capacity: budget.capacity * self.config.ip_factor,
+            refill_per_second: budget.refill_per_second * self.config.ip_factor,
+        };
+        self.take(BucketOwner::Ip(ip), route, ip_budget)
+            .await
+            .map_err(|wait| ("ip", wait))
+    }
+
+    pub fn status(&self) -> RateLimitStatusResponse {
+        let mut routes: Vec<RouteCounterResponse> = self
+            .counters
+            .lock()
+            .expect("Rate limit counters poisoned")
+            .iter()
+            .map(|(route, counter)| RouteCounterResponse {
+                route: route.clone(),
+                allowed: counter.allowed.load(Ordering::Relaxed),
+                rejected: counter.rejected.load(Ordering::Relaxed),
+            })
+            .collect();
+        routes.sort_by(|a, b| a.route.cmp(&b.route));
+
+        RateLimitStatusResponse {
+            enabled: self.config.enabled,
+            buckets: self.buckets.entry_count(),
+            routes,
+        }
+    }
+}
+
+/// Middleware enforcing per-session and per-IP token buckets, rejecting with
+/// `APIError::TooManyRequests`.
+/// CAVEAT: Must be wrapped inside the `SessionMiddleware` and the
+/// `negotiate_error`.
+pub async fn rate_limit<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let limiter = match req.app_data::<web::Data<Arc<RateLimiter>>>() {
+        Some(limiter) if limiter.config.enabled => limiter.clone(),
+        _ => return next.call(req).await.map(|res| res.map_into_left_body()),
+    };
+
+    // Unmatched paths share one bucket, so scanners cannot grow the counters.
+    let route = req.match_pattern().map_or_else(
+        || "[unmatched]".to_string(),
+        |pattern| unversioned(&pattern).to_string(),
+    );
+    let user_id = req.get_session().get::<i32>(SESSION_USER_ID).ok().flatten();
+    let ip = client_ip(req.request());
+
+    let counter = limiter.counter(&route);
+    match limiter.check(user_id, ip, &route).await {
+        Ok(()) => {
+            counter.allowed.fetch_add(1, Ordering::Relaxed);
+            next.call(req).await.map(|res| res.map_into_left_body())
+        }
+        Err((scope, wait)) => {
+            counter.rejected.fetch_add(1, Ordering::Relaxed);
+            let error = APIError::TooManyRequests {
+                route,
+                scope,
+                retry_after: wait.as_secs().saturating_add(1),
+            };
+            Ok(req.error_response(error).map_into_right_body())
+        }
+    }
+}
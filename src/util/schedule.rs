@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+
+use crate::models::{Decipher, PuzzleBase};
+
+use super::api_util::APIError;
+
+/// Where `now` falls in an `available_from` / `available_until` window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    NotYet(DateTime<Utc>),
+    Open,
+    Closed(DateTime<Utc>),
+}
+
+impl Availability {
+    /// An unset bound does not limit the window.
+    ///
+    /// ```
+    /// use chrono::{TimeZone, Utc};
+    /// use server::util::schedule::Availability;
+    ///
+    /// let from = Utc.timestamp_opt(100, 0).unwrap();
+    /// let now = Utc.timestamp_opt(50, 0).unwrap();
+    /// assert_eq!(Availability::at(Some(from), None, now), Availability::NotYet(from));
+    /// assert_eq!(Availability::at(None, Some(now), now), Availability::Closed(now));
+    /// assert_eq!(Availability::at(None, None, now), Availability::Open);
+    /// ```
+    pub fn at(
+        from: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        match (from, until) {
+            (Some(from), _) if now < from => Availability::NotYet(from),
+            (_, Some(until)) if now >= until => Availability::Closed(until),
+            _ => Availability::Open,
+        }
+    }
+
+    pub fn check(self) -> Result<(), APIError> {
+        match self {
+            Availability::Open => Ok(()),
+            _ => Err(APIError::NotAvailable),
+        }
+    }
+}
+
+impl PuzzleBase {
+    pub fn availability(&self, now: DateTime<Utc>) -> Availability {
+        Availability::at(self.available_from, self.available_until, now)
+    }
+}
+
+impl Decipher {
+    pub fn availability(&self, now: DateTime<Utc>) -> Availability {
+        Availability::at(self.available_from, self.available_until, now)
+    }
+}
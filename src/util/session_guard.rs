@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use actix_session::SessionExt;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use log::info;
+
+use super::api_util::{SESSION_USER_ID, SESSION_VERSION};
+use super::cache::Cache;
+
+/// Middleware clearing sessions issued before the user's `session_version`
+/// was bumped, e.g. by a password reset.
+/// CAVEAT: Must be wrapped inside the `SessionMiddleware`.
+pub async fn session_guard<B>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, Error>
+where
+    B: MessageBody + 'static,
+{
+    if let Some(cache) = req.app_data::<web::Data<Arc<Cache>>>() {
+        let session = req.get_session();
+        if let Ok(Some(user_id)) = session.get::<i32>(SESSION_USER_ID) {
+            // Sessions from before versioning count as version 0.
+            let version = session
+                .get::<i32>(SESSION_VERSION)
+                .ok()
+                .flatten()
+                .unwrap_or(0);
+
+            // A failed lookup is already logged, the session is kept then.
+            if let Ok(current) = cache.session_version_cache.get(user_id).await {
+                if current != Some(version) {
+                    info!("Revoked session of user {user_id} (version {version})");
+                    session.purge();
+                }
+            }
+        }
+    }
+    next.call(req).await
+}
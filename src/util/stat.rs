@@ -1,12 +1,18 @@
+use std::collections::HashMap;
 use std::ops::DerefMut;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use diesel::prelude::*;
-use diesel::sql_types::{BigInt, Integer};
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text, Timestamptz};
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::{DecipherId, PuzzleId, TeamId};
 
 use super::api_util::{log_server_error, APIError, ERROR_DB_UNKNOWN};
+use super::config::config;
+use super::economy::WRONG_ANSWER_DESP;
 
 #[derive(Serialize, QueryableByName, Queryable, Clone)] // 添加 QueryableByName
 pub struct CountItem {
@@ -25,7 +31,48 @@ pub struct PuzzleStatistic {
     pub time: DateTime<Utc>,
 }
 
+/// The counts kept up to date by the triggers on `submission` and `unlock`,
+/// a row per puzzle.
 pub async fn fetch_statistic<C>(conn: &mut C) -> Result<PuzzleStatistic, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::puzzle::dsl as puzzle_dsl;
+    use crate::schema::puzzle_summary::dsl as summary_dsl;
+
+    let data = puzzle_dsl::puzzle
+        .left_join(summary_dsl::puzzle_summary)
+        .order(puzzle_dsl::id)
+        .select((
+            puzzle_dsl::id,
+            puzzle_dsl::decipher,
+            summary_dsl::teams_passed.nullable(),
+            summary_dsl::teams_unlocked.nullable(),
+        ))
+        .load::<(i32, i32, Option<i64>, Option<i64>)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .map(|(puzzle_id, decipher, passed, unlocked)| CountItem {
+            puzzle_id,
+            decipher,
+            teams_passed: passed.unwrap_or(0),
+            teams_unlocked: unlocked.unwrap_or(0),
+        })
+        .collect();
+
+    Ok(PuzzleStatistic {
+        data,
+        time: Utc::now(),
+    })
+}
+
+/// Counts everything again as of `frozen_at`. The summary cannot tell when a
+/// team passed, so a frozen scoreboard is computed once.
+pub async fn fetch_frozen_statistic<C>(
+    conn: &mut C,
+    frozen_at: DateTime<Utc>,
+) -> Result<PuzzleStatistic, APIError>
 where
     C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
 {
@@ -35,8 +82,8 @@ where
         SELECT 
             p.id AS puzzle_id,
             p.decipher,
-            COALESCE(COUNT(DISTINCT CASE WHEN s.depth = 0 THEN s.team ELSE NULL END), 0) AS teams_passed,
-            COALESCE(COUNT(DISTINCT u.team), 0) AS teams_unlocked
+            COALESCE(COUNT(DISTINCT CASE WHEN s.depth = 0 AND s.time <= $1 THEN s.team ELSE NULL END), 0) AS teams_passed,
+            COALESCE(COUNT(DISTINCT CASE WHEN u.unlocked_at <= $1 THEN u.team ELSE NULL END), 0) AS teams_unlocked
         FROM puzzle AS p
         LEFT JOIN submission AS s
             ON p.id = s.puzzle
@@ -45,7 +92,8 @@ where
         GROUP BY p.id, p.decipher
         ORDER BY p.id;
     "#,
-    );
+    )
+    .bind::<Timestamptz, _>(frozen_at);
 
     // Execute the query and map results to CountItem
     let data: Vec<CountItem> = query
@@ -62,41 +110,429 @@ where
     Ok(statistic)
 }
 
-pub async fn fetch_statistic_for_team<C>(
-    conn: &mut C,
-    team_id: i32,
-) -> Result<PuzzleStatistic, APIError>
+/// What a team did on each puzzle, by a query per table.
+#[derive(Default)]
+pub struct TeamProgress {
+    pub unlocks: HashMap<DecipherId, i32>,
+    pub submissions: HashMap<PuzzleId, Vec<(i32, i64, DateTime<Utc>)>>, // (depth, reward, time)
+    pub penalties: HashMap<PuzzleId, DateTime<Utc>>,                    // until, if not over
+    pub toasts: HashMap<PuzzleId, Vec<(String, DateTime<Utc>)>>,
+    pub oracles: HashMap<PuzzleId, (usize, usize)>, // (all, active)
+}
+
+pub async fn fetch_team_progress<C>(conn: &mut C, team_id: TeamId) -> Result<TeamProgress, APIError>
 where
     C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
 {
-    // Define the query to fetch puzzle stats for a specific team
-    let query = diesel::sql_query(r#"
-        SELECT 
-            p.id AS puzzle_id,
-            p.decipher,
-            COALESCE(SUM(CASE WHEN s.depth = 0 AND s.team = $1 THEN 1 ELSE 0 END), 0) AS teams_passed,
-            COALESCE(COUNT(DISTINCT CASE WHEN u.team = $1 THEN u.team ELSE NULL END), 0) AS teams_unlocked
-        FROM puzzle AS p
-        LEFT JOIN submission AS s
-            ON p.id = s.puzzle
-        LEFT JOIN unlock AS u
-            ON p.decipher = u.decipher
-        GROUP BY p.id, p.decipher
-        ORDER BY p.id;
-    "#)
-    .bind::<diesel::sql_types::Integer, _>(team_id);
+    use crate::schema::{
+        oracle, other_answer, other_answer_submission, submission, unlock, wrong_answer_cnt,
+    };
 
-    // Execute the query and map results to CountItem
-    let data: Vec<CountItem> = query
-        .load(conn)
+    let unlocks = unlock::table
+        .filter(unlock::team.eq(team_id))
+        .select((unlock::decipher, unlock::level))
+        .load::<(i32, i32)>(conn)
         .await
-        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .collect();
 
-    // Create the statistic object
-    let statistic = PuzzleStatistic {
+    let penalties = wrong_answer_cnt::table
+        .filter(wrong_answer_cnt::team.eq(team_id))
+        .filter(wrong_answer_cnt::time_penalty_until.gt(Utc::now()))
+        .select((
+            wrong_answer_cnt::puzzle,
+            wrong_answer_cnt::time_penalty_until,
+        ))
+        .load::<(i32, DateTime<Utc>)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .collect();
+
+    let mut progress = TeamProgress {
+        unlocks,
+        penalties,
+        ..Default::default()
+    };
+
+    for (puzzle_id, depth, reward, time) in submission::table
+        .filter(submission::team.eq(team_id))
+        .order(submission::time)
+        .select((
+            submission::puzzle,
+            submission::depth,
+            submission::reward,
+            submission::time,
+        ))
+        .load::<(i32, i32, i64, DateTime<Utc>)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+    {
+        progress
+            .submissions
+            .entry(puzzle_id)
+            .or_default()
+            .push((depth, reward, time));
+    }
+
+    for (puzzle_id, content, time) in other_answer_submission::table
+        .inner_join(other_answer::table)
+        .filter(other_answer_submission::team.eq(team_id))
+        .order(other_answer_submission::time)
+        .select((
+            other_answer::puzzle,
+            other_answer::content,
+            other_answer_submission::time,
+        ))
+        .load::<(i32, String, DateTime<Utc>)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+    {
+        progress
+            .toasts
+            .entry(puzzle_id)
+            .or_default()
+            .push((content, time));
+    }
+
+    for (puzzle_id, active) in oracle::table
+        .filter(oracle::team.eq(team_id))
+        .select((oracle::puzzle, oracle::active))
+        .load::<(i32, bool)>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+    {
+        let (all, active_count) = progress.oracles.entry(puzzle_id).or_default();
+        *all += 1;
+        if active {
+            *active_count += 1;
+        }
+    }
+
+    Ok(progress)
+}
+
+#[derive(Serialize, ToSchema, Clone)]
+pub struct FirstSolve {
+    pub team: TeamId,
+    pub time: i64, // unix timestamp in seconds
+}
+
+#[derive(Serialize, ToSchema, Clone)]
+pub struct LevelCount {
+    pub depth: i32, // 0 for the final answer
+    pub teams: i64,
+}
+
+/// What puzzle authors want to know about a puzzle. Staff teams are left out.
+#[derive(Serialize, ToSchema, Clone)]
+pub struct PuzzleSolveStat {
+    pub puzzle_id: PuzzleId,
+    pub first_solve: Option<FirstSolve>,
+    pub median_solve_seconds: Option<f64>, // from the unlock to the final answer
+    pub wrong_guesses: i64,
+    pub levels: Vec<LevelCount>,
+    pub oracles: i64,
+    pub oracle_teams: i64,
+}
+
+pub struct SolveStatistic {
+    pub data: Vec<PuzzleSolveStat>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(QueryableByName)]
+struct FirstSolveRow {
+    #[diesel(sql_type = Integer)]
+    puzzle: i32,
+    #[diesel(sql_type = Integer)]
+    team: i32,
+    #[diesel(sql_type = Timestamptz)]
+    time: DateTime<Utc>,
+}
+
+#[derive(QueryableByName)]
+struct MedianRow {
+    #[diesel(sql_type = Integer)]
+    puzzle: i32,
+    #[diesel(sql_type = Double)]
+    median_seconds: f64,
+}
+
+#[derive(QueryableByName)]
+struct PuzzleCountRow {
+    #[diesel(sql_type = Integer)]
+    puzzle: i32,
+    #[diesel(sql_type = Integer)]
+    depth: i32,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = BigInt)]
+    teams: i64,
+}
+
+pub async fn fetch_solve_statistic<C>(conn: &mut C) -> Result<SolveStatistic, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    use crate::schema::puzzle::dsl::*;
+
+    let mut data: Vec<PuzzleSolveStat> = puzzle
+        .order(id)
+        .select(id)
+        .load::<i32>(conn)
+        .await
+        .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?
+        .into_iter()
+        .map(|puzzle_id| PuzzleSolveStat {
+            puzzle_id,
+            first_solve: None,
+            median_solve_seconds: None,
+            wrong_guesses: 0,
+            levels: vec![],
+            oracles: 0,
+            oracle_teams: 0,
+        })
+        .collect();
+    let index: HashMap<PuzzleId, usize> = data
+        .iter()
+        .enumerate()
+        .map(|(i, stat)| (stat.puzzle_id, i))
+        .collect();
+    let stat_of = |puzzle_id: i32| index.get(&puzzle_id).copied();
+
+    let first_solves: Vec<FirstSolveRow> = diesel::sql_query(
+        r#"
+        SELECT DISTINCT ON (s.puzzle) s.puzzle, s.team, s.time
+        FROM submission AS s
+        JOIN team AS t ON t.id = s.team
+        WHERE s.depth = 0 AND NOT t.is_staff
+        ORDER BY s.puzzle, s.time, s.id;
+    "#,
+    )
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+    for row in first_solves {
+        if let Some(i) = stat_of(row.puzzle) {
+            data[i].first_solve = Some(FirstSolve {
+                team: row.team,
+                time: row.time.timestamp(),
+            });
+        }
+    }
+
+    let medians: Vec<MedianRow> = diesel::sql_query(
+        r#"
+        SELECT
+            s.puzzle,
+            percentile_cont(0.5) WITHIN GROUP (
+                ORDER BY EXTRACT(EPOCH FROM s.time - u.unlocked_at)
+            ) AS median_seconds
+        FROM submission AS s
+        JOIN team AS t ON t.id = s.team
+        JOIN puzzle AS p ON p.id = s.puzzle
+        JOIN unlock AS u ON u.team = s.team AND u.decipher = p.decipher
+        WHERE s.depth = 0 AND NOT t.is_staff
+        GROUP BY s.puzzle;
+    "#,
+    )
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+    for row in medians {
+        if let Some(i) = stat_of(row.puzzle) {
+            data[i].median_solve_seconds = Some(row.median_seconds);
+        }
+    }
+
+    // The depth is unused by the wrong guesses and oracles.
+    let wrong_guesses: Vec<PuzzleCountRow> = diesel::sql_query(
+        r#"
+        SELECT w.puzzle, 0 AS depth, COALESCE(SUM(w.wrong_attempts), 0)::BIGINT AS count, COUNT(*) AS teams
+        FROM wrong_answer_cnt AS w
+        JOIN team AS t ON t.id = w.team
+        WHERE NOT t.is_staff
+        GROUP BY w.puzzle;
+    "#,
+    )
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+    for row in wrong_guesses {
+        if let Some(i) = stat_of(row.puzzle) {
+            data[i].wrong_guesses = row.count;
+        }
+    }
+
+    let levels: Vec<PuzzleCountRow> = diesel::sql_query(
+        r#"
+        SELECT s.puzzle, s.depth, COUNT(*) AS count, COUNT(DISTINCT s.team) AS teams
+        FROM submission AS s
+        JOIN team AS t ON t.id = s.team
+        WHERE NOT t.is_staff
+        GROUP BY s.puzzle, s.depth
+        ORDER BY s.puzzle, s.depth DESC;
+    "#,
+    )
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+    for row in levels {
+        if let Some(i) = stat_of(row.puzzle) {
+            data[i].levels.push(LevelCount {
+                depth: row.depth,
+                teams: row.teams,
+            });
+        }
+    }
+
+    let oracles: Vec<PuzzleCountRow> = diesel::sql_query(
+        r#"
+        SELECT o.puzzle, 0 AS depth, COUNT(*) AS count, COUNT(DISTINCT o.team) AS teams
+        FROM oracle AS o
+        JOIN team AS t ON t.id = o.team
+        WHERE NOT t.is_staff
+        GROUP BY o.puzzle;
+    "#,
+    )
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+    for row in oracles {
+        if let Some(i) = stat_of(row.puzzle) {
+            data[i].oracles = row.count;
+            data[i].oracle_teams = row.teams;
+        }
+    }
+
+    Ok(SolveStatistic {
         data,
         time: Utc::now(),
+    })
+}
+
+/// Which activity `/staff_activity` charts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ActivityFilter {
+    pub granularity: TimeDelta,
+    pub puzzle: Option<PuzzleId>,
+    pub team: Option<TeamId>, // staff teams are left out unless asked for
+}
+
+#[derive(Serialize, ToSchema, Clone, Default)]
+pub struct ActivityBucket {
+    pub start: i64, // unix timestamp in seconds
+    pub submissions: i64,
+    pub wrong_answers: i64,
+    pub unlocks: i64, // purchased, not granted for free
+    pub oracles: i64,
+    /// The token flow is not filtered by puzzle.
+    pub tokens_minted: i64,
+    pub tokens_burned: i64,
+}
+
+pub struct Activity {
+    pub data: Vec<ActivityBucket>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(QueryableByName)]
+struct ActivityRow {
+    #[diesel(sql_type = Integer)]
+    kind: i32,
+    #[diesel(sql_type = BigInt)]
+    bucket: i64,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+    #[diesel(sql_type = BigInt)]
+    amount: i64,
+}
+
+/// Buckets counted from the epoch, every one from the first active to the last.
+pub async fn fetch_activity<C>(conn: &mut C, filter: ActivityFilter) -> Result<Activity, APIError>
+where
+    C: DerefMut<Target = AsyncPgConnection> + std::marker::Send,
+{
+    let epoch = config().game.epoch;
+    let seconds = filter.granularity.num_seconds().max(1);
+
+    let rows: Vec<ActivityRow> = diesel::sql_query(
+        r#"
+        WITH counted AS (
+            SELECT id FROM team WHERE ($3::INT IS NULL AND NOT is_staff) OR id = $3
+        )
+        SELECT 0 AS kind, FLOOR(EXTRACT(EPOCH FROM s.time - $1) / $4)::BIGINT AS bucket,
+            COUNT(*) AS count, 0::BIGINT AS amount
+        FROM submission AS s
+        WHERE s.team IN (SELECT id FROM counted) AND ($2::INT IS NULL OR s.puzzle = $2)
+        GROUP BY 2
+        UNION ALL
+        SELECT 1, FLOOR(EXTRACT(EPOCH FROM x.time - $1) / $4)::BIGINT, COUNT(*), 0
+        FROM transaction AS x
+        WHERE x.team IN (SELECT id FROM counted)
+            AND CASE WHEN $2::INT IS NULL THEN starts_with(x.desp, $5) ELSE x.desp = $5 || $2 END
+        GROUP BY 2
+        UNION ALL
+        SELECT 2, FLOOR(EXTRACT(EPOCH FROM x.time - $1) / $4)::BIGINT, COUNT(*), 0
+        FROM transaction AS x
+        WHERE x.purchase_ref IS NOT NULL
+            AND x.team IN (SELECT id FROM counted)
+            AND ($2::INT IS NULL OR x.purchase_ref IN (SELECT decipher FROM puzzle WHERE id = $2))
+        GROUP BY 2
+        UNION ALL
+        SELECT 3, FLOOR(EXTRACT(EPOCH FROM o.created_at - $1) / $4)::BIGINT, COUNT(*), 0
+        FROM oracle AS o
+        WHERE o.team IN (SELECT id FROM counted) AND ($2::INT IS NULL OR o.puzzle = $2)
+        GROUP BY 2
+        UNION ALL
+        SELECT CASE WHEN x.amount > 0 THEN 4 ELSE 5 END,
+            FLOOR(EXTRACT(EPOCH FROM x.time - $1) / $4)::BIGINT, COUNT(*), SUM(ABS(x.amount))::BIGINT
+        FROM transaction AS x
+        WHERE x.amount <> 0 AND x.team IN (SELECT id FROM counted)
+        GROUP BY 1, 2;
+    "#,
+    )
+    .bind::<Timestamptz, _>(epoch)
+    .bind::<Nullable<Integer>, _>(filter.puzzle)
+    .bind::<Nullable<Integer>, _>(filter.team)
+    .bind::<BigInt, _>(seconds)
+    .bind::<Text, _>(WRONG_ANSWER_DESP)
+    .load(conn)
+    .await
+    .map_err(|e| log_server_error(e, "stat", ERROR_DB_UNKNOWN))?;
+
+    let (Some(first), Some(last)) = (
+        rows.iter().map(|row| row.bucket).min(),
+        rows.iter().map(|row| row.bucket).max(),
+    ) else {
+        return Ok(Activity {
+            data: vec![],
+            time: Utc::now(),
+        });
     };
 
-    Ok(statistic)
+    let mut data: Vec<ActivityBucket> = (first..=last)
+        .map(|bucket| ActivityBucket {
+            start: epoch.timestamp() + bucket * seconds,
+            ..Default::default()
+        })
+        .collect();
+    for row in rows {
+        let bucket = &mut data[(row.bucket - first) as usize];
+        match row.kind {
+            0 => bucket.submissions += row.count,
+            1 => bucket.wrong_answers += row.count,
+            2 => bucket.unlocks += row.count,
+            3 => bucket.oracles += row.count,
+            4 => bucket.tokens_minted += row.amount,
+            _ => bucket.tokens_burned += row.amount,
+        }
+    }
+
+    Ok(Activity {
+        data,
+        time: Utc::now(),
+    })
 }
@@ -1,4 +1,5 @@
 import hashlib
+import hmac
 import random
 from random import SystemRandom
 import time
@@ -30,7 +31,7 @@ load_env()
 # Hash: SHA256 256
 
 
-salt = os.environ["REGISTER_TOKEN"]
+salt = os.environ.get("REGISTER_TOKEN", "")
 
 # print(salt)
 
@@ -82,6 +83,24 @@ def get_token(version, mark, openid):
     
 token = get_token(2, 4, genenrate_openid())
 
+
+# v2 (HMAC-SHA256), keyed by one of REGISTER_TOKENS="<key id>:<secret>,..."
+# format (= 2): 8 bits
+# key id: 8 bits
+# openid: 168 bits
+# time: in seconds, 32 bits
+# mark: 8 bits
+# nonce: 32 bits
+# HMAC: 256 bits
+def get_token_v2(key_id, secret, mark, openid):
+    payload = bytes([2, key_id & 0xff])
+    payload += openid.to_bytes(21, byteorder='big', signed=False)
+    payload += (int(time.time()) & 0xffffffff).to_bytes(4, byteorder='big')
+    payload += bytes([mark & 0xff])
+    payload += SystemRandom().getrandbits(32).to_bytes(4, byteorder='big')
+
+    return payload + hmac.new(secret.encode("utf-8"), payload, hashlib.sha256).digest()
+
 # print(token.hex())
 
 # raw  028312ec8e8bdd45bc29818a2a83ddd009a15107243901b81742042f47e6428f
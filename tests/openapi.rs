@@ -0,0 +1,49 @@
+use actix_web::http::{Method, StatusCode};
+use actix_web::{test, web, App, HttpResponse};
+use server::api::{configure, ApiDoc};
+use utoipa::OpenApi;
+
+/// Answered by the app for requests no service matches, which no service
+/// answers with.
+const UNROUTED: StatusCode = StatusCode::IM_A_TEAPOT;
+
+/// `configure` registers the services and `ApiDoc` documents them from the
+/// same lists, so it is left to check that every documented operation is
+/// routed by `configure` under its method and path.
+#[actix_web::test]
+async fn every_schema_has_a_service() {
+    let app = test::init_service(
+        App::new()
+            .configure(configure)
+            .default_service(web::to(|| async { HttpResponse::build(UNROUTED).finish() })),
+    )
+    .await;
+
+    let doc = ApiDoc::openapi();
+    assert!(!doc.paths.paths.is_empty());
+
+    let mut unrouted = Vec::new();
+    for (path, item) in &doc.paths.paths {
+        let operations = [
+            (Method::GET, &item.get),
+            (Method::POST, &item.post),
+            (Method::PUT, &item.put),
+            (Method::DELETE, &item.delete),
+            (Method::PATCH, &item.patch),
+        ];
+        for (method, _) in operations.iter().filter(|(_, op)| op.is_some()) {
+            let req = test::TestRequest::default()
+                .method(method.clone())
+                .uri(path)
+                .to_request();
+            if test::call_service(&app, req).await.status() == UNROUTED {
+                unrouted.push(format!("{method} {path}"));
+            }
+        }
+    }
+
+    assert!(
+        unrouted.is_empty(),
+        "Schemas without a service: {unrouted:?}"
+    );
+}